@@ -0,0 +1,353 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors;
+
+use crate::effect::EffectKind;
+use crate::game::{Game, TORCH_RADIUS};
+use crate::map::{is_blocked, MAP_HEIGHT, MAP_WIDTH};
+use crate::object::{mut_two, Object, PLAYER};
+use crate::path::{has_line_of_fire, has_line_of_sight, safety_map};
+
+/// Ranged monsters back away from the player when they get closer than this.
+const KEEP_DISTANCE: f32 = 3.0;
+
+/// Monsters run once their HP drops below this fraction of the maximum, and stop
+/// running once it is back above the second one.
+const FLEE_HP_FRACTION: f32 = 0.25;
+const RECOVERED_HP_FRACTION: f32 = 0.5;
+/// HP a fleeing monster recovers each turn it spends out of the player's sight.
+const FLEE_RECOVERY: i32 = 1;
+
+/// Chance each turn that a sleeping monster with the player in view wakes up.
+const WAKE_CHANCE: f64 = 0.1;
+/// Turns a monster keeps hunting after losing sight of the player.
+const HUNT_TURNS: i32 = 20;
+/// How far away a wandering monster picks its next destination.
+const WANDER_RADIUS: i32 = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ai {
+    Basic,
+    /// Keeps its distance and shoots from up to `range` tiles away.
+    Ranged {
+        range: i32,
+        damage: i32,
+        projectile: String,
+    },
+    /// Running from the player until it has recovered or is cornered.
+    Fleeing {
+        previous_ai: Box<Ai>,
+    },
+}
+
+/// What a monster knows about the player, independent of how it fights.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Awareness {
+    Asleep,
+    Wandering {
+        destination: Option<(i32, i32)>,
+    },
+    Hunting {
+        last_seen: (i32, i32),
+        /// Turns left before it gives up if it doesn't see the player again.
+        turns_left: i32,
+    },
+}
+
+impl Awareness {
+    /// Hunting with a fresh trail leading to `last_seen`.
+    pub fn hunting(last_seen: (i32, i32)) -> Self {
+        Awareness::Hunting {
+            last_seen,
+            turns_left: HUNT_TURNS,
+        }
+    }
+}
+
+pub fn ai_take_turn(monster_id: usize, game: &mut Game, objects: &mut [Object]) {
+    use Ai::*;
+    // Effects override whatever the monster would rather be doing.
+    if objects[monster_id].has_effect(EffectKind::Confusion) {
+        Object::move_by(
+            monster_id,
+            game.rng.gen_range(-1..=1),
+            game.rng.gen_range(-1..=1),
+            &game.map,
+            objects,
+        );
+        return;
+    }
+    if objects[monster_id].has_effect(EffectKind::Fear) {
+        let safety = safety_map(objects[PLAYER].position(), &game.map);
+        if let Some((x, y)) = flee_step(monster_id, &safety, game, objects) {
+            objects[monster_id].set_position(x, y);
+        }
+        return;
+    }
+
+    if let Some(ai) = objects[monster_id].ai.take() {
+        // Only a monster that knows where the player is has anything to run from.
+        let hunting = matches!(objects[monster_id].awareness, Awareness::Hunting { .. });
+        let new_ai = match ai {
+            Fleeing { previous_ai } => {
+                let safety = safety_map(objects[PLAYER].position(), &game.map);
+                ai_fleeing(monster_id, game, objects, previous_ai, &safety)
+            }
+            ai if hunting && hp_fraction(monster_id, game, objects) < FLEE_HP_FRACTION => {
+                let safety = safety_map(objects[PLAYER].position(), &game.map);
+                if flee_step(monster_id, &safety, game, objects).is_some() {
+                    let (x, y) = objects[monster_id].position();
+                    if game.fov.is_in_fov(x, y) {
+                        game.messages.add(
+                            format!("{} turns to flee!", objects[monster_id].name),
+                            colors::LIGHT_BLUE,
+                        );
+                    }
+                    ai_fleeing(monster_id, game, objects, Box::new(ai), &safety)
+                } else {
+                    perceive_and_act(monster_id, ai, game, objects)
+                }
+            }
+            ai => perceive_and_act(monster_id, ai, game, objects),
+        };
+        objects[monster_id].ai = Some(new_ai);
+    }
+}
+
+/// Acts on what the monster knows, if it sees the player at all.
+fn perceive_and_act(monster_id: usize, ai: Ai, game: &mut Game, objects: &mut [Object]) -> Ai {
+    if perceive(monster_id, game, objects) {
+        act(monster_id, ai, game, objects)
+    } else {
+        ai
+    }
+}
+
+fn act(monster_id: usize, ai: Ai, game: &mut Game, objects: &mut [Object]) -> Ai {
+    use Ai::*;
+    match ai {
+        Basic => ai_basic(monster_id, game, objects),
+        Ranged {
+            range,
+            damage,
+            projectile,
+        } => ai_ranged(monster_id, game, objects, range, damage, projectile),
+        Fleeing { previous_ai } => {
+            let safety = safety_map(objects[PLAYER].position(), &game.map);
+            ai_fleeing(monster_id, game, objects, previous_ai, &safety)
+        }
+    }
+}
+
+fn hp_fraction(monster_id: usize, game: &Game, objects: &[Object]) -> f32 {
+    let hp = objects[monster_id].fighter.map_or(0, |f| f.hp);
+    hp as f32 / objects[monster_id].max_hp(game) as f32
+}
+
+/// The free neighbouring tile that gets the monster furthest out of the player's
+/// reach according to `safety`, or `None` if it is cornered.
+fn flee_step(
+    monster_id: usize,
+    safety: &[Vec<i32>],
+    game: &Game,
+    objects: &[Object],
+) -> Option<(i32, i32)> {
+    let safety_at = |(x, y): (i32, i32)| safety[x as usize][y as usize];
+    let (x, y) = objects[monster_id].position();
+    (-1..=1)
+        .flat_map(|dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+        .filter(|&(x, y)| {
+            (0..MAP_WIDTH).contains(&x)
+                && (0..MAP_HEIGHT).contains(&y)
+                && !is_blocked(x, y, &game.map, objects)
+        })
+        .min_by_key(|&tile| safety_at(tile))
+        .filter(|&tile| safety_at(tile) < safety_at((x, y)))
+}
+
+/// Updates what the monster knows about the player and returns whether it sees
+/// them. If it doesn't, it has already spent its turn sleeping, wandering or
+/// searching.
+fn perceive(monster_id: usize, game: &mut Game, objects: &mut [Object]) -> bool {
+    let sees = sees_player(monster_id, game, objects);
+    let hunting = Awareness::hunting(objects[PLAYER].position());
+    match objects[monster_id].awareness {
+        Awareness::Asleep => {
+            if sees && game.rng.gen_bool(WAKE_CHANCE) {
+                game.messages.add(
+                    format!("{} wakes up!", objects[monster_id].name),
+                    colors::LIGHT_RED,
+                );
+                objects[monster_id].awareness = hunting;
+            }
+            false
+        }
+        _ if sees => {
+            objects[monster_id].awareness = hunting;
+            true
+        }
+        Awareness::Wandering { destination } => {
+            wander(monster_id, destination, game, objects);
+            false
+        }
+        Awareness::Hunting {
+            last_seen,
+            turns_left,
+        } => {
+            if turns_left <= 0 {
+                objects[monster_id].awareness = Awareness::Wandering { destination: None };
+                return false;
+            }
+            if objects[monster_id].position() == last_seen {
+                // Lost the trail; cast about for it.
+                Object::move_by(
+                    monster_id,
+                    game.rng.gen_range(-1..=1),
+                    game.rng.gen_range(-1..=1),
+                    &game.map,
+                    objects,
+                );
+            } else {
+                Object::move_astar(monster_id, last_seen.0, last_seen.1, &game.map, objects);
+            }
+            objects[monster_id].awareness = Awareness::Hunting {
+                last_seen,
+                turns_left: turns_left - 1,
+            };
+            false
+        }
+    }
+}
+
+/// Heads for `destination`, picking a new one nearby once there or stuck.
+fn wander(
+    monster_id: usize,
+    destination: Option<(i32, i32)>,
+    game: &mut Game,
+    objects: &mut [Object],
+) {
+    let (x, y) = objects[monster_id].position();
+    let destination = match destination {
+        Some(destination) if destination != (x, y) => destination,
+        _ => {
+            let destination = (
+                (x + game.rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS)).clamp(0, MAP_WIDTH - 1),
+                (y + game.rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS)).clamp(0, MAP_HEIGHT - 1),
+            );
+            if is_blocked(destination.0, destination.1, &game.map, objects) {
+                return;
+            }
+            destination
+        }
+    };
+    Object::move_astar(monster_id, destination.0, destination.1, &game.map, objects);
+    let moved = objects[monster_id].position() != (x, y);
+    objects[monster_id].awareness = Awareness::Wandering {
+        destination: if moved { Some(destination) } else { None },
+    };
+}
+
+pub fn sees_player(monster_id: usize, game: &Game, objects: &[Object]) -> bool {
+    let distance = objects[monster_id].distance_to(&objects[PLAYER]);
+    // A blind monster only notices the player by bumping into them.
+    if objects[monster_id].has_effect(EffectKind::Blind) {
+        distance < 2.0
+    } else {
+        // Not `game.fov`, which shrinks when the player is blinded.
+        distance <= TORCH_RADIUS as f32
+            && has_line_of_sight(
+                objects[monster_id].position(),
+                objects[PLAYER].position(),
+                &game.map,
+            )
+    }
+}
+
+fn ai_basic(monster_id: usize, game: &mut Game, objects: &mut [Object]) -> Ai {
+    if sees_player(monster_id, game, objects) {
+        if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
+            let (player_x, player_y) = objects[PLAYER].position();
+            Object::move_astar(monster_id, player_x, player_y, &game.map, objects);
+        } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
+            let (monster, player) = mut_two(monster_id, PLAYER, objects);
+            monster.attack(player, game);
+        }
+    }
+    Ai::Basic
+}
+
+fn ai_ranged(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    range: i32,
+    damage: i32,
+    projectile: String,
+) -> Ai {
+    if sees_player(monster_id, game, objects) {
+        let (player_x, player_y) = objects[PLAYER].position();
+        let distance = objects[monster_id].distance_to(&objects[PLAYER]);
+        let player_alive = objects[PLAYER].fighter.map_or(false, |f| f.hp > 0);
+        let retreated = distance < KEEP_DISTANCE
+            && Object::move_away(monster_id, player_x, player_y, &game.map, objects);
+        if !retreated {
+            if distance < 2.0 && player_alive {
+                let (monster, player) = mut_two(monster_id, PLAYER, objects);
+                monster.attack(player, game);
+            } else if distance <= range as f32
+                && player_alive
+                && has_line_of_fire(
+                    objects[monster_id].position(),
+                    (player_x, player_y),
+                    &game.map,
+                    objects,
+                )
+            {
+                let (monster, player) = mut_two(monster_id, PLAYER, objects);
+                monster.shoot(player, damage, &projectile, game);
+            } else {
+                Object::move_astar(monster_id, player_x, player_y, &game.map, objects);
+            }
+        }
+    }
+    Ai::Ranged {
+        range,
+        damage,
+        projectile,
+    }
+}
+
+fn ai_fleeing(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    previous_ai: Box<Ai>,
+    safety: &[Vec<i32>],
+) -> Ai {
+    if hp_fraction(monster_id, game, objects) >= RECOVERED_HP_FRACTION {
+        return *previous_ai;
+    }
+    match flee_step(monster_id, safety, game, objects) {
+        Some((x, y)) => {
+            objects[monster_id].set_position(x, y);
+            // Out of the player's sight it gets to catch its breath.
+            if !game.fov.is_in_fov(x, y) {
+                objects[monster_id].heal(FLEE_RECOVERY, game);
+            }
+            Ai::Fleeing { previous_ai }
+        }
+        None => {
+            let (x, y) = objects[monster_id].position();
+            if game.fov.is_in_fov(x, y) {
+                game.messages.add(
+                    format!(
+                        "{} is cornered and turns to fight!",
+                        objects[monster_id].name
+                    ),
+                    colors::LIGHT_RED,
+                );
+            }
+            act(monster_id, *previous_ai, game, objects)
+        }
+    }
+}
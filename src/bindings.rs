@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+use tcod::input::{Key, KeyCode};
+
+pub const BINDINGS_FILE: &str = "bindings.toml";
+
+/// Used when there is no bindings file next to the game.
+const DEFAULT_BINDINGS: &str = include_str!("../bindings.toml");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveNorthWest,
+    MoveNorthEast,
+    MoveSouthWest,
+    MoveSouthEast,
+    Wait,
+    PickUp,
+    Descend,
+    Inventory,
+    Drop,
+    MessageLog,
+    ToggleFullscreen,
+    Exit,
+}
+impl Action {
+    pub fn direction(self) -> Option<(i32, i32)> {
+        use Action::*;
+        match self {
+            MoveNorth => Some((0, -1)),
+            MoveSouth => Some((0, 1)),
+            MoveWest => Some((-1, 0)),
+            MoveEast => Some((1, 0)),
+            MoveNorthWest => Some((-1, -1)),
+            MoveNorthEast => Some((1, -1)),
+            MoveSouthWest => Some((-1, 1)),
+            MoveSouthEast => Some((1, 1)),
+            _ => None,
+        }
+    }
+}
+
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("move_north", Action::MoveNorth),
+    ("move_south", Action::MoveSouth),
+    ("move_west", Action::MoveWest),
+    ("move_east", Action::MoveEast),
+    ("move_north_west", Action::MoveNorthWest),
+    ("move_north_east", Action::MoveNorthEast),
+    ("move_south_west", Action::MoveSouthWest),
+    ("move_south_east", Action::MoveSouthEast),
+    ("wait", Action::Wait),
+    ("pick_up", Action::PickUp),
+    ("descend", Action::Descend),
+    ("inventory", Action::Inventory),
+    ("drop", Action::Drop),
+    ("message_log", Action::MessageLog),
+    ("toggle_fullscreen", Action::ToggleFullscreen),
+    ("exit", Action::Exit),
+];
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Enter", KeyCode::Enter),
+    ("Escape", KeyCode::Escape),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Space", KeyCode::Spacebar),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("NumPad0", KeyCode::NumPad0),
+    ("NumPad1", KeyCode::NumPad1),
+    ("NumPad2", KeyCode::NumPad2),
+    ("NumPad3", KeyCode::NumPad3),
+    ("NumPad4", KeyCode::NumPad4),
+    ("NumPad5", KeyCode::NumPad5),
+    ("NumPad6", KeyCode::NumPad6),
+    ("NumPad7", KeyCode::NumPad7),
+    ("NumPad8", KeyCode::NumPad8),
+    ("NumPad9", KeyCode::NumPad9),
+    ("NumPadEnter", KeyCode::NumPadEnter),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+];
+
+/// A key as written in the bindings file.
+#[derive(Clone, Debug, PartialEq)]
+enum Trigger {
+    Code {
+        code: KeyCode,
+        alt: bool,
+        ctrl: bool,
+    },
+    Text(String),
+}
+impl Trigger {
+    fn parse(spec: &str) -> Option<Trigger> {
+        let mut alt = false;
+        let mut ctrl = false;
+        let mut name = spec;
+        loop {
+            if let Some(rest) = name.strip_prefix("Alt+") {
+                alt = true;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("Ctrl+") {
+                ctrl = true;
+                name = rest;
+            } else {
+                break;
+            }
+        }
+
+        if let Some(&(_, code)) = KEY_NAMES.iter().find(|(key_name, _)| *key_name == name) {
+            Some(Trigger::Code { code, alt, ctrl })
+        } else if name.chars().count() == 1 && !alt && !ctrl {
+            Some(Trigger::Text(name.into()))
+        } else {
+            None
+        }
+    }
+
+    fn matches(&self, key: Key) -> bool {
+        match self {
+            Trigger::Code { code, alt, ctrl } => {
+                key.code == *code && key.alt == *alt && key.ctrl == *ctrl
+            }
+            Trigger::Text(text) => key.code == KeyCode::Text && key.text() == text,
+        }
+    }
+}
+
+pub struct Bindings {
+    bindings: Vec<(Trigger, Action)>,
+}
+impl Bindings {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(BINDINGS_FILE) {
+            Ok(contents) => {
+                Bindings::parse(&contents).map_err(|e| format!("{}: {}", BINDINGS_FILE, e).into())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Bindings::parse(DEFAULT_BINDINGS),
+            Err(e) => Err(format!("{}: {}", BINDINGS_FILE, e).into()),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let table: BTreeMap<String, String> = toml::from_str(contents)?;
+        let mut bindings = vec![];
+        for (key, action_name) in table {
+            let trigger = Trigger::parse(&key).ok_or_else(|| {
+                format!(
+                    "unknown key {:?}; expected a single character or one of: {}",
+                    key,
+                    KEY_NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+            let action = ACTION_NAMES
+                .iter()
+                .find(|(name, _)| *name == action_name)
+                .map(|&(_, action)| action)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown action {:?} for key {:?}; expected one of: {}",
+                        action_name,
+                        key,
+                        ACTION_NAMES
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?;
+            bindings.push((trigger, action));
+        }
+        Ok(Bindings { bindings })
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(trigger, _)| trigger.matches(key))
+            .map(|&(_, action)| action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(contents: &str) -> String {
+        Bindings::parse(contents)
+            .err()
+            .expect("bindings should not parse")
+            .to_string()
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = parse_error(r#"Banana = "wait""#);
+        assert!(error.contains(r#"unknown key "Banana""#), "{}", error);
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        let error = parse_error(r#"g = "fly""#);
+        assert!(error.contains(r#"unknown action "fly""#), "{}", error);
+    }
+
+    #[test]
+    fn modifiers_prefix_key_names() {
+        assert_eq!(
+            Trigger::parse("Alt+Enter"),
+            Some(Trigger::Code {
+                code: KeyCode::Enter,
+                alt: true,
+                ctrl: false,
+            })
+        );
+        assert_eq!(
+            Trigger::parse("Ctrl+Alt+F1"),
+            Some(Trigger::Code {
+                code: KeyCode::F1,
+                alt: true,
+                ctrl: true,
+            })
+        );
+        assert_eq!(Trigger::parse("Shift+Enter"), None);
+    }
+
+    #[test]
+    fn single_characters_are_text_keys() {
+        assert_eq!(Trigger::parse("g"), Some(Trigger::Text("g".into())));
+        assert_eq!(Trigger::parse("G"), Some(Trigger::Text("G".into())));
+        assert_eq!(Trigger::parse("gg"), None);
+        // Modifiers only go with named keys.
+        assert_eq!(Trigger::parse("Ctrl+g"), None);
+    }
+
+    #[test]
+    fn shipped_bindings_load() {
+        let bindings = match Bindings::parse(DEFAULT_BINDINGS) {
+            Ok(bindings) => bindings,
+            Err(e) => panic!("{}", e),
+        };
+        let escape = Trigger::parse("Escape").unwrap();
+        assert!(bindings.bindings.contains(&(escape, Action::Exit)));
+    }
+}
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use tcod::colors::{self, Color};
+
+use crate::game::Game;
+use crate::object::{Object, PLAYER};
+
+const POISON_DAMAGE: i32 = 1;
+const REGENERATION_AMOUNT: i32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EffectKind {
+    Poison,
+    Regeneration,
+    Haste,
+    Slow,
+    Blind,
+    Fear,
+    Confusion,
+}
+impl EffectKind {
+    /// Short label for the status panel.
+    pub fn name(self) -> &'static str {
+        use EffectKind::*;
+        match self {
+            Poison => "Poisoned",
+            Regeneration => "Regenerating",
+            Haste => "Hasted",
+            Slow => "Slowed",
+            Blind => "Blind",
+            Fear => "Afraid",
+            Confusion => "Confused",
+        }
+    }
+
+    pub fn start_message(self) -> &'static str {
+        use EffectKind::*;
+        match self {
+            Poison => "is poisoned",
+            Regeneration => "starts to regenerate",
+            Haste => "speeds up",
+            Slow => "slows down",
+            Blind => "is blinded",
+            Fear => "is terrified",
+            Confusion => "looks confused",
+        }
+    }
+
+    fn end_message(self) -> &'static str {
+        use EffectKind::*;
+        match self {
+            Poison => "is no longer poisoned",
+            Regeneration => "stops regenerating",
+            Haste => "is no longer hasted",
+            Slow => "is no longer slowed",
+            Blind => "can see again",
+            Fear => "regains courage",
+            Confusion => "is no longer confused",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        use EffectKind::*;
+        match self {
+            Poison => colors::CHARTREUSE,
+            Regeneration => colors::LIGHT_VIOLET,
+            Haste | Slow => colors::LIGHT_CYAN,
+            Blind => colors::GREY,
+            Fear => colors::LIGHT_ORANGE,
+            Confusion => colors::LIGHT_GREEN,
+        }
+    }
+
+    pub fn power_bonus(self) -> i32 {
+        match self {
+            EffectKind::Poison => -1,
+            _ => 0,
+        }
+    }
+
+    pub fn defense_bonus(self) -> i32 {
+        match self {
+            EffectKind::Haste => 1,
+            EffectKind::Slow => -1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub turns: i32,
+}
+
+/// Lets the object's effects do their work for one turn and removes those that
+/// ran out.
+pub fn tick_effects(id: usize, game: &mut Game, objects: &mut [Object]) {
+    let kinds: Vec<_> = objects[id].effects.iter().map(|e| e.kind).collect();
+    for kind in kinds {
+        match kind {
+            EffectKind::Poison => {
+                if let Some(xp) = objects[id].take_damage(POISON_DAMAGE, game) {
+                    if id != PLAYER {
+                        objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+                    }
+                }
+            }
+            EffectKind::Regeneration => objects[id].heal(REGENERATION_AMOUNT, game),
+            _ => {}
+        }
+    }
+
+    let object = &mut objects[id];
+    if object.fighter.is_none() {
+        object.effects.clear();
+        return;
+    }
+    for effect in object.effects.iter_mut() {
+        effect.turns -= 1;
+    }
+    for effect in object.effects.iter().filter(|e| e.turns <= 0) {
+        game.messages.add(
+            format!("{} {}.", object.name, effect.kind.end_message()),
+            effect.kind.color(),
+        );
+    }
+    object.effects.retain(|e| e.turns > 0);
+}
@@ -0,0 +1,457 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors::{self, Color};
+use tcod::map::{FovAlgorithm, Map as FovMap};
+
+use crate::ai::ai_take_turn;
+use crate::effect::{tick_effects, EffectKind};
+use crate::item::{drop_item, pick_item_up, use_item};
+use crate::map::{make_map, Generator, Map, MAP_HEIGHT, MAP_WIDTH};
+use crate::noise::{make_noise, COMBAT_NOISE, FOOTSTEP_NOISE};
+use crate::object::{mut_two, DeathCallback, Fighter, Object, PLAYER};
+use crate::template::Templates;
+
+const FOV_ALGORITHM: FovAlgorithm = FovAlgorithm::Basic;
+const FOV_LIGHT_WALLS: bool = true;
+/// How far the player sees, and how far monsters see the player.
+pub const TORCH_RADIUS: i32 = 10;
+const BLIND_RADIUS: i32 = 1;
+
+const LEVEL_UP_BASE: i32 = 200;
+const LEVEL_UP_FACTOR: i32 = 150;
+
+/// Energy gained per turn at normal speed, and the price of an ordinary action.
+pub const NORMAL_SPEED: i32 = 100;
+const ACTION_COST: i32 = NORMAL_SPEED;
+const USE_ITEM_COST: i32 = 150;
+const DROP_COST: i32 = 50;
+
+pub const SAVE_FILE: &str = "savegame";
+
+/// How many messages are kept for the log unless `--message-limit` says otherwise.
+pub const DEFAULT_MESSAGE_LIMIT: usize = 500;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    pub text: String,
+    pub color: Color,
+    /// How many times in a row the same message was added.
+    pub count: u32,
+}
+impl Message {
+    /// The text as it should be shown, with the repeat counter if there is one.
+    pub fn display(&self) -> String {
+        if self.count > 1 {
+            format!("{} (x{})", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Messages {
+    messages: VecDeque<Message>,
+    #[serde(default = "default_message_limit")]
+    limit: usize,
+}
+impl Messages {
+    pub fn new() -> Self {
+        Self::with_limit(DEFAULT_MESSAGE_LIMIT)
+    }
+
+    /// Only the newest `limit` messages are kept.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            limit,
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.truncate();
+    }
+
+    /// Repeats of the newest message only bump its counter.
+    pub fn add<T: Into<String>>(&mut self, message: T, color: Color) {
+        let text = message.into();
+        if let Some(last) = self.messages.back_mut() {
+            if last.text == text && last.color == color {
+                last.count += 1;
+                return;
+            }
+        }
+        self.messages.push_back(Message {
+            text,
+            color,
+            count: 1,
+        });
+        self.truncate();
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Message> {
+        self.messages.iter()
+    }
+
+    fn truncate(&mut self) {
+        while self.messages.len() > self.limit {
+            self.messages.pop_front();
+        }
+    }
+}
+impl Default for Messages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn default_message_limit() -> usize {
+    DEFAULT_MESSAGE_LIMIT
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Game {
+    pub map: Map,
+    /// Where the stairs down from this level are.
+    pub stairs: (i32, i32),
+    pub messages: Messages,
+    pub inventory: Vec<Object>,
+    pub dungeon_level: u32,
+    pub seed: u32,
+    pub rng: ChaCha8Rng,
+    /// Turns of game time passed so far, at normal speed.
+    pub turn: u64,
+    /// How every level of this game is laid out.
+    pub generator: Generator,
+    /// Saved along with the game so it keeps spawning what it started with.
+    pub templates: Templates,
+    /// Rebuilt from `map` by `initialise_fov` rather than saved.
+    #[serde(skip, default = "new_fov_map")]
+    pub fov: FovMap,
+}
+impl Game {
+    pub fn initialise_fov(&mut self) {
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                self.fov.set(
+                    x,
+                    y,
+                    !self.map[x as usize][y as usize].block_sight,
+                    !self.map[x as usize][y as usize].blocked,
+                );
+            }
+        }
+    }
+
+    pub fn compute_fov(&mut self, objects: &[Object]) {
+        let player = &objects[PLAYER];
+        let radius = if player.has_effect(EffectKind::Blind) {
+            BLIND_RADIUS
+        } else {
+            TORCH_RADIUS
+        };
+        self.fov
+            .compute_fov(player.x, player.y, radius, FOV_LIGHT_WALLS, FOV_ALGORITHM);
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if self.fov.is_in_fov(x, y) {
+                    self.map[x as usize][y as usize].explored = true;
+                }
+            }
+        }
+    }
+}
+
+fn new_fov_map() -> FovMap {
+    FovMap::new(MAP_WIDTH, MAP_HEIGHT)
+}
+
+/// Everything the player can do in the world, already resolved from raw input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    MoveOrAttack {
+        dx: i32,
+        dy: i32,
+    },
+    Wait,
+    PickUp,
+    Descend,
+    UseItem {
+        inventory_id: usize,
+        target: Option<(i32, i32)>,
+    },
+    Drop {
+        inventory_id: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stat {
+    Constitution,
+    Strength,
+    Agility,
+}
+
+pub fn new_game(
+    seed: u32,
+    generator: Generator,
+    templates: Templates,
+    message_limit: usize,
+) -> (Game, Vec<Object>) {
+    let mut player = Object::new(25, 23, '@', "Player", colors::WHITE, true);
+    player.alive = true;
+    player.energy = ACTION_COST;
+    player.fighter = Some(Fighter {
+        base_max_hp: 30,
+        hp: 30,
+        base_defense: 2,
+        base_power: 5,
+        xp: 0,
+        on_death: DeathCallback::Player,
+        on_hit: None,
+    });
+    let mut objects = vec![player];
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed as u64);
+    let (map, stairs) = make_map(&mut objects, &templates, generator, 1, &mut rng);
+    let mut game = Game {
+        map,
+        stairs,
+        messages: Messages::with_limit(message_limit),
+        inventory: vec![],
+        dungeon_level: 1,
+        seed,
+        rng,
+        turn: 0,
+        generator,
+        templates,
+        fov: new_fov_map(),
+    };
+    game.initialise_fov();
+    game.compute_fov(&objects);
+
+    game.messages.add(
+        "Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.",
+        colors::RED,
+    );
+
+    (game, objects)
+}
+
+/// Carries out the player's command and, if it took time, runs the world until the
+/// player has the energy to act again. Returns whether any time passed.
+pub fn play_turn(command: Command, game: &mut Game, objects: &mut Vec<Object>) -> bool {
+    let cost = match command {
+        Command::MoveOrAttack { dx, dy } => {
+            player_move_or_attack(dx, dy, game, objects);
+            Some(ACTION_COST)
+        }
+        Command::Wait => Some(ACTION_COST),
+        Command::PickUp => {
+            let item_id = objects.iter().position(|object| {
+                object.item.is_some() && object.position() == objects[PLAYER].position()
+            });
+            if let Some(item_id) = item_id {
+                pick_item_up(item_id, game, objects);
+            }
+            None
+        }
+        Command::Descend => {
+            if objects[PLAYER].position() == game.stairs {
+                next_level(game, objects);
+            }
+            None
+        }
+        // Commands don't have to come from the inventory menu, so the item may not exist.
+        Command::UseItem { inventory_id, .. } | Command::Drop { inventory_id }
+            if inventory_id >= game.inventory.len() =>
+        {
+            None
+        }
+        Command::UseItem {
+            inventory_id,
+            target,
+        } => use_item(inventory_id, target, game, objects).then_some(USE_ITEM_COST),
+        Command::Drop { inventory_id } => {
+            drop_item(inventory_id, game, objects);
+            Some(DROP_COST)
+        }
+    };
+    game.compute_fov(objects);
+
+    if let Some(cost) = cost {
+        objects[PLAYER].energy -= cost;
+        run_until_player_ready(game, objects);
+    }
+    cost.is_some()
+}
+
+/// Every turn each fighter gains energy equal to its speed, and monsters act for as
+/// long as they can pay for it, so a fast one may act several times per turn and a
+/// slow one only every few turns.
+fn run_until_player_ready(game: &mut Game, objects: &mut [Object]) {
+    while objects[PLAYER].alive && objects[PLAYER].energy < ACTION_COST {
+        game.turn += 1;
+        for id in 0..objects.len() {
+            if objects[id].fighter.is_some() {
+                objects[id].energy += objects[id].speed();
+            }
+            tick_effects(id, game, objects);
+        }
+        // Blindness may just have worn off.
+        game.compute_fov(objects);
+
+        for id in 0..objects.len() {
+            while objects[id].ai.is_some() && objects[id].energy >= ACTION_COST {
+                ai_take_turn(id, game, objects);
+                objects[id].energy -= ACTION_COST;
+            }
+        }
+    }
+}
+
+fn player_move_or_attack(dx: i32, dy: i32, game: &mut Game, objects: &mut [Object]) {
+    let (dx, dy) = if objects[PLAYER].has_effect(EffectKind::Confusion) {
+        (game.rng.gen_range(-1..=1), game.rng.gen_range(-1..=1))
+    } else {
+        (dx, dy)
+    };
+    let x = objects[PLAYER].x + dx;
+    let y = objects[PLAYER].y + dy;
+    let target_id = objects
+        .iter()
+        .position(|object| object.fighter.is_some() && object.position() == (x, y));
+    match target_id {
+        // Stumbling on the spot doesn't mean attacking yourself.
+        Some(target_id) if target_id != PLAYER => {
+            let (player, target) = mut_two(PLAYER, target_id, objects);
+            player.attack(target, game);
+            make_noise((x, y), COMBAT_NOISE, game, objects);
+        }
+        _ => {
+            Object::move_by(PLAYER, dx, dy, &game.map, objects);
+            make_noise(objects[PLAYER].position(), FOOTSTEP_NOISE, game, objects);
+        }
+    }
+}
+
+fn next_level(game: &mut Game, objects: &mut Vec<Object>) {
+    game.messages.add(
+        "You take a moment to rest, and recover your strength.",
+        colors::VIOLET,
+    );
+    let heal_hp = objects[PLAYER].max_hp(game) / 2;
+    objects[PLAYER].heal(heal_hp, game);
+
+    game.messages.add(
+        "After a rare moment of peace, you descend deeper into \
+         the heart of the dungeon...",
+        colors::RED,
+    );
+    game.dungeon_level += 1;
+    let (map, stairs) = make_map(
+        objects,
+        &game.templates,
+        game.generator,
+        game.dungeon_level,
+        &mut game.rng,
+    );
+    game.map = map;
+    game.stairs = stairs;
+    game.initialise_fov();
+}
+
+pub fn level_up_xp(level: i32) -> i32 {
+    LEVEL_UP_BASE + level * LEVEL_UP_FACTOR
+}
+
+pub fn can_level_up(objects: &[Object]) -> bool {
+    let player = &objects[PLAYER];
+    player.fighter.map_or(0, |f| f.xp) >= level_up_xp(player.level)
+}
+
+pub fn level_up(stat: Stat, game: &mut Game, objects: &mut [Object]) {
+    let player = &mut objects[PLAYER];
+    let required_xp = level_up_xp(player.level);
+    player.level += 1;
+    game.messages.add(
+        format!(
+            "Your battle skills grow stronger! You reached level {}!",
+            player.level
+        ),
+        colors::YELLOW,
+    );
+
+    let fighter = player.fighter.as_mut().unwrap();
+    fighter.xp -= required_xp;
+    match stat {
+        Stat::Constitution => {
+            fighter.base_max_hp += 20;
+            fighter.hp += 20;
+        }
+        Stat::Strength => fighter.base_power += 1,
+        Stat::Agility => fighter.base_defense += 1,
+    }
+}
+
+pub fn save_game(game: &Game, objects: &[Object]) -> Result<(), Box<dyn Error>> {
+    let save_data = serde_json::to_string(&(game, objects))?;
+    let mut file = File::create(SAVE_FILE)?;
+    file.write_all(save_data.as_bytes())?;
+    Ok(())
+}
+
+pub fn load_game() -> Result<(Game, Vec<Object>), Box<dyn Error>> {
+    let mut save_data = String::new();
+    let mut file = File::open(SAVE_FILE)?;
+    file.read_to_string(&mut save_data)?;
+    let (mut game, objects) = serde_json::from_str::<(Game, Vec<Object>)>(&save_data)?;
+    game.initialise_fov();
+    game.compute_fov(&objects);
+    Ok((game, objects))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_messages_collapse() {
+        let mut messages = Messages::new();
+        messages.add("You hit the orc.", colors::WHITE);
+        messages.add("You hit the orc.", colors::WHITE);
+        messages.add("You hit the orc.", colors::RED);
+        messages.add("You hit the orc.", colors::WHITE);
+
+        let shown: Vec<_> = messages.iter().map(Message::display).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "You hit the orc. (x2)",
+                "You hit the orc.",
+                "You hit the orc.",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_newest_messages_are_kept() {
+        let mut messages = Messages::with_limit(2);
+        for text in ["one", "two", "three"] {
+            messages.add(text, colors::WHITE);
+        }
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["two", "three"]);
+
+        messages.set_limit(1);
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["three"]);
+    }
+}
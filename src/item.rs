@@ -0,0 +1,341 @@
+use std::cmp;
+
+use serde::{Deserialize, Serialize};
+use tcod::colors;
+
+use crate::effect::Effect;
+use crate::game::Game;
+use crate::map::{MAP_HEIGHT, MAP_WIDTH};
+use crate::noise::{make_noise, FIREBALL_NOISE, LIGHTNING_NOISE};
+use crate::object::{Object, Slot, PLAYER};
+
+const HEAL_AMOUNT: i32 = 4;
+const LIGHTNING_DAMAGE: i32 = 20;
+const LIGHTNING_RANGE: i32 = 5;
+const HEX_RANGE: i32 = 8;
+const FIREBALL_RADIUS: i32 = 3;
+const FIREBALL_DAMAGE: i32 = 12;
+const FIREBALL_RANGE: i32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Item {
+    Heal,
+    Lightning,
+    Fireball,
+    Equipment,
+    /// Puts the effect on whoever drinks it.
+    Potion(Effect),
+    /// Puts the effect on a monster of the player's choosing.
+    Hex(Effect),
+}
+impl Item {
+    /// What the player has to pick before the item can be used, if anything.
+    pub fn targeting(self) -> Option<Targeting> {
+        match self {
+            Item::Lightning => Some(Targeting::Monster {
+                range: LIGHTNING_RANGE,
+            }),
+            Item::Hex(_) => Some(Targeting::Monster { range: HEX_RANGE }),
+            Item::Fireball => Some(Targeting::Tile {
+                range: FIREBALL_RANGE,
+            }),
+            Item::Heal | Item::Equipment | Item::Potion(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Targeting {
+    Monster { range: i32 },
+    Tile { range: i32 },
+}
+
+pub enum UseResult {
+    UsedUp,
+    UsedAndKept,
+    Cancelled,
+}
+
+pub fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<Object>) {
+    if game.inventory.len() >= 26 {
+        game.messages.add(
+            format!(
+                "Your inventory is full, cannot pick up {}.",
+                objects[object_id].name
+            ),
+            colors::RED,
+        );
+    } else {
+        let item = objects.swap_remove(object_id);
+        game.messages
+            .add(format!("You picked up a {}!", item.name), colors::GREEN);
+        game.inventory.push(item);
+    }
+}
+
+pub fn drop_item(inventory_id: usize, game: &mut Game, objects: &mut Vec<Object>) {
+    let mut item = game.inventory.remove(inventory_id);
+    if item.equipment.is_some() {
+        item.dequip(&mut game.messages);
+        clamp_hp(game, objects);
+    }
+    item.set_position(objects[PLAYER].x, objects[PLAYER].y);
+    game.messages
+        .add(format!("You dropped a {}.", item.name), colors::YELLOW);
+    objects.push(item);
+}
+
+/// Uses the item, aimed at `target` if it needs one. Returns whether it took a turn.
+pub fn use_item(
+    inventory_id: usize,
+    target: Option<(i32, i32)>,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> bool {
+    if let Some(item) = game.inventory[inventory_id].item {
+        let on_use = match item {
+            Item::Heal => cast_heal,
+            Item::Lightning => cast_lightning,
+            Item::Fireball => cast_fireball,
+            Item::Equipment => toggle_equipment,
+            Item::Potion(_) => drink_potion,
+            Item::Hex(_) => cast_hex,
+        };
+        match on_use(inventory_id, target, objects, game) {
+            UseResult::UsedUp => {
+                game.inventory.remove(inventory_id);
+                true
+            }
+            UseResult::UsedAndKept => true,
+            UseResult::Cancelled => {
+                game.messages.add("Cancelled", colors::WHITE);
+                false
+            }
+        }
+    } else {
+        game.messages.add(
+            format!("The {} cannot be used.", game.inventory[inventory_id].name),
+            colors::WHITE,
+        );
+        false
+    }
+}
+
+pub fn closest_monster(max_range: i32, game: &Game, objects: &[Object]) -> Option<usize> {
+    let mut closest_enemy = None;
+    let mut closest_dist = (max_range + 1) as f32;
+    for (id, object) in objects.iter().enumerate() {
+        if id != PLAYER
+            && object.fighter.is_some()
+            && object.ai.is_some()
+            && game.fov.is_in_fov(object.x, object.y)
+        {
+            let dist = objects[PLAYER].distance_to(object);
+            if dist < closest_dist {
+                closest_enemy = Some(id);
+                closest_dist = dist;
+            }
+        }
+    }
+    closest_enemy
+}
+
+pub fn target_in_range(x: i32, y: i32, max_range: i32, game: &Game, objects: &[Object]) -> bool {
+    (0..MAP_WIDTH).contains(&x)
+        && (0..MAP_HEIGHT).contains(&y)
+        && game.fov.is_in_fov(x, y)
+        && objects[PLAYER].distance(x, y) <= max_range as f32
+}
+
+pub fn monster_at(x: i32, y: i32, objects: &[Object]) -> Option<usize> {
+    objects
+        .iter()
+        .enumerate()
+        .find(|&(id, object)| {
+            id != PLAYER && object.fighter.is_some() && object.position() == (x, y)
+        })
+        .map(|(id, _)| id)
+}
+
+fn target_monster(
+    target: Option<(i32, i32)>,
+    max_range: i32,
+    game: &Game,
+    objects: &[Object],
+) -> Option<usize> {
+    let (x, y) = target?;
+    if target_in_range(x, y, max_range, game, objects) {
+        monster_at(x, y, objects)
+    } else {
+        None
+    }
+}
+
+fn cast_heal(
+    _inventory_id: usize,
+    _target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    let player = &mut objects[PLAYER];
+    if let Some(fighter) = player.fighter {
+        if fighter.hp == player.max_hp(game) {
+            game.messages
+                .add("You are already at full health.", colors::RED);
+            return UseResult::Cancelled;
+        }
+        game.messages
+            .add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
+        player.heal(HEAL_AMOUNT, game);
+        UseResult::UsedUp
+    } else {
+        UseResult::Cancelled
+    }
+}
+
+fn cast_lightning(
+    _inventory_id: usize,
+    target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    let monster_id = target_monster(target, LIGHTNING_RANGE, game, objects);
+    if let Some(monster_id) = monster_id {
+        game.messages.add(
+            format!(
+                "A lightning bolt strikes the {} with a loud thunder! \
+                The damage is {} hit points.",
+                objects[monster_id].name, LIGHTNING_DAMAGE
+            ),
+            colors::LIGHT_BLUE,
+        );
+        if let Some(xp) = objects[monster_id].take_damage(LIGHTNING_DAMAGE, game) {
+            objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+        }
+        make_noise(objects[PLAYER].position(), LIGHTNING_NOISE, game, objects);
+        UseResult::UsedUp
+    } else {
+        game.messages
+            .add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+fn cast_hex(
+    inventory_id: usize,
+    target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    let effect = match game.inventory[inventory_id].item {
+        Some(Item::Hex(effect)) => effect,
+        _ => return UseResult::Cancelled,
+    };
+    let monster_id = target_monster(target, HEX_RANGE, game, objects);
+    if let Some(monster_id) = monster_id {
+        objects[monster_id].apply_effect(effect, game);
+        UseResult::UsedUp
+    } else {
+        game.messages
+            .add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+fn cast_fireball(
+    _inventory_id: usize,
+    target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    let (x, y) = match target {
+        Some((x, y)) if target_in_range(x, y, FIREBALL_RANGE, game, objects) => (x, y),
+        _ => {
+            game.messages
+                .add("That spot is out of the fireball's range.", colors::RED);
+            return UseResult::Cancelled;
+        }
+    };
+    game.messages.add(
+        format!(
+            "The fireball explodes, burning everything within {} tiles!",
+            FIREBALL_RADIUS
+        ),
+        colors::ORANGE,
+    );
+
+    let mut xp_to_gain = 0;
+    for (id, object) in objects.iter_mut().enumerate() {
+        if object.distance(x, y) <= FIREBALL_RADIUS as f32 && object.fighter.is_some() {
+            game.messages.add(
+                format!(
+                    "The {} gets burned for {} hit points.",
+                    object.name, FIREBALL_DAMAGE
+                ),
+                colors::ORANGE,
+            );
+            if let Some(xp) = object.take_damage(FIREBALL_DAMAGE, game) {
+                if id != PLAYER {
+                    xp_to_gain += xp;
+                }
+            }
+        }
+    }
+    objects[PLAYER].fighter.as_mut().unwrap().xp += xp_to_gain;
+    make_noise((x, y), FIREBALL_NOISE, game, objects);
+
+    UseResult::UsedUp
+}
+
+fn drink_potion(
+    inventory_id: usize,
+    _target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    match game.inventory[inventory_id].item {
+        Some(Item::Potion(effect)) => {
+            objects[PLAYER].apply_effect(effect, game);
+            UseResult::UsedUp
+        }
+        _ => UseResult::Cancelled,
+    }
+}
+
+fn toggle_equipment(
+    inventory_id: usize,
+    _target: Option<(i32, i32)>,
+    objects: &mut [Object],
+    game: &mut Game,
+) -> UseResult {
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+    if equipment.equipped {
+        game.inventory[inventory_id].dequip(&mut game.messages);
+    } else {
+        if let Some(current) = get_equipped_in_slot(equipment.slot, &game.inventory) {
+            game.inventory[current].dequip(&mut game.messages);
+        }
+        game.inventory[inventory_id].equip(&mut game.messages);
+    }
+
+    clamp_hp(game, objects);
+    UseResult::UsedAndKept
+}
+
+/// Taking equipment off can leave the player with more HP than their new maximum.
+fn clamp_hp(game: &Game, objects: &mut [Object]) {
+    let max_hp = objects[PLAYER].max_hp(game);
+    if let Some(fighter) = objects[PLAYER].fighter.as_mut() {
+        fighter.hp = cmp::min(fighter.hp, max_hp);
+    }
+}
+
+pub fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Option<usize> {
+    inventory.iter().position(|item| {
+        item.equipment
+            .map_or(false, |e| e.equipped && e.slot == slot)
+    })
+}
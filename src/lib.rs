@@ -0,0 +1,11 @@
+//! The game simulation: everything needed to advance a turn without a window.
+
+pub mod ai;
+pub mod effect;
+pub mod game;
+pub mod item;
+pub mod map;
+pub mod noise;
+pub mod object;
+pub mod path;
+pub mod template;
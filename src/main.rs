@@ -1,11 +1,24 @@
-use std::cmp::{self, max, min};
-
-use rand::Rng;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+mod bindings;
+
+use bindings::{Action, Bindings};
+use roguelike::ai::Awareness;
+use roguelike::game::{self, Command, Game, Messages, Stat, DEFAULT_MESSAGE_LIMIT, SAVE_FILE};
+use roguelike::item::{self, Targeting};
+use roguelike::map::{Generator, MAP_HEIGHT, MAP_WIDTH};
+use roguelike::object::{Object, PLAYER};
+use roguelike::template::Templates;
 use tcod::{
     colors,
     console::{blit, Offscreen, Root},
     input::{self, Event, Key, KeyCode, Mouse},
-    map::{FovAlgorithm, Map as FovMap},
+    map::Map as FovMap,
     system::set_fps,
     BackgroundFlag, Color, Console, FontLayout, FontType, TextAlignment,
 };
@@ -13,12 +26,11 @@ use tcod::{
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
 
-const MAP_WIDTH: i32 = 80;
-const MAP_HEIGHT: i32 = 43;
-
 const INVENTORY_WIDTH: i32 = 50;
+const LEVEL_SCREEN_WIDTH: i32 = 40;
+const MAIN_MENU_WIDTH: i32 = 24;
 
-const PANEL_HEIGHT: i32 = 5;
+const PANEL_HEIGHT: i32 = 7;
 const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
 const BAR_WIDTH: i32 = 20;
 
@@ -26,9 +38,10 @@ const MSG_X: i32 = BAR_WIDTH + 2;
 const MSG_WIDTH: i32 = SCREEN_WIDTH - BAR_WIDTH - 2;
 const MSG_HEIGHT: i32 = PANEL_HEIGHT - 1;
 
-const LIMIT_FPS: i32 = 20;
+/// First row of the message log below its title.
+const LOG_TOP: i32 = 2;
 
-const PLAYER: usize = 0;
+const LIMIT_FPS: i32 = 20;
 
 const COLOR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100 };
 const COLOR_LIGHT_WALL: Color = Color {
@@ -46,234 +59,21 @@ const COLOR_LIGHT_GROUND: Color = Color {
     g: 180,
     b: 50,
 };
-
-const ROOM_MAX_SIZE: i32 = 10;
-const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
-const MAX_ROOM_MONSTERS: i32 = 3;
-const MAX_ROOM_ITEMS: i32 = 2;
-
-const HEAL_AMOUNT: i32 = 4;
-const LIGHTNING_DAMAGE: i32 = 20;
-const LIGHTNING_RANGE: i32 = 5;
-const CONFUSE_RANGE: i32 = 8;
-const CONFUSE_NUM_TURNS: i32 = 10;
-
-const FOV_ALGORITHM: FovAlgorithm = FovAlgorithm::Basic;
-const FOV_LIGHT_WALLS: bool = true;
-const TORCH_RADIUS: i32 = 10;
+const COLOR_TARGET_RANGE: Color = Color {
+    r: 60,
+    g: 60,
+    b: 60,
+};
+const COLOR_TARGET_VALID: Color = colors::LIGHT_GREEN;
+const COLOR_TARGET_INVALID: Color = colors::RED;
 
 struct Tcod {
     root: Root,
     console: Offscreen,
     panel: Offscreen,
-    fov: FovMap,
     key: Key,
     mouse: Mouse,
-}
-
-struct Messages {
-    messages: Vec<(String, Color)>,
-}
-impl Messages {
-    pub fn new() -> Self {
-        Self { messages: vec![] }
-    }
-
-    pub fn add<T: Into<String>>(&mut self, message: T, color: Color) {
-        self.messages.push((message.into(), color));
-    }
-
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &(String, Color)> {
-        self.messages.iter()
-    }
-}
-
-#[derive(Debug)]
-struct Object {
-    x: i32,
-    y: i32,
-    char: char,
-    color: colors::Color,
-    name: String,
-    blocks: bool,
-    alive: bool,
-    fighter: Option<Fighter>,
-    ai: Option<Ai>,
-    item: Option<Item>,
-}
-impl Object {
-    pub fn new(x: i32, y: i32, char: char, name: &str, color: colors::Color, blocks: bool) -> Self {
-        Object {
-            x,
-            y,
-            char,
-            color,
-            name: name.into(),
-            blocks,
-            alive: false,
-            fighter: None,
-            ai: None,
-            item: None,
-        }
-    }
-
-    pub fn set_position(&mut self, x: i32, y: i32) {
-        self.x = x;
-        self.y = y;
-    }
-
-    pub fn position(&self) -> (i32, i32) {
-        (self.x, self.y)
-    }
-
-    pub fn distance_to(&self, other: &Object) -> f32 {
-        let dx = other.x - self.x;
-        let dy = other.y - self.y;
-        ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
-    }
-
-    pub fn take_damage(&mut self, damage: i32, game: &mut Game) {
-        if let Some(fighter) = self.fighter.as_mut() {
-            if damage > 0 {
-                fighter.hp -= damage;
-            }
-        }
-        if let Some(fighter) = self.fighter {
-            if fighter.hp <= 0 {
-                self.alive = false;
-                fighter.on_death.callback(self, game);
-            }
-        }
-    }
-
-    pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
-        let damage = self.fighter.map_or(0, |f| f.power) - target.fighter.map_or(0, |f| f.defense);
-        if damage > 0 {
-            game.messages.add(
-                format!(
-                    "{} attacks {} for {} hit points.",
-                    self.name, target.name, damage
-                ),
-                colors::WHITE,
-            );
-            target.take_damage(damage, game);
-        } else {
-            game.messages.add(
-                format!(
-                    "{} attacks {} but it has no effect!",
-                    self.name, target.name
-                ),
-                colors::WHITE,
-            );
-        }
-    }
-
-    pub fn heal(&mut self, amount: i32) {
-        if let Some(ref mut fighter) = self.fighter {
-            fighter.hp = cmp::min(fighter.max_hp, fighter.hp + amount);
-        }
-    }
-
-    pub fn draw(&self, con: &mut dyn Console) {
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
-    }
-
-    pub fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
-        let (x, y) = objects[id].position();
-        let (new_x, new_y) = (x + dx, y + dy);
-        if !is_blocked(new_x, new_y, map, objects) {
-            objects[id].set_position(new_x, new_y);
-        }
-    }
-
-    pub fn move_towards(
-        id: usize,
-        target_x: i32,
-        target_y: i32,
-        map: &Map,
-        objects: &mut [Object],
-    ) {
-        let dx = target_x - objects[id].x;
-        let dy = target_y - objects[id].y;
-        let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
-        let dx = (dx as f32 / distance).round() as i32;
-        let dy = (dy as f32 / distance).round() as i32;
-        Object::move_by(id, dx, dy, map, objects);
-    }
-}
-
-#[derive(Clone, Copy, Debug)]
-struct Tile {
-    blocked: bool,
-    explored: bool,
-    block_sight: bool,
-}
-impl Tile {
-    pub fn empty() -> Self {
-        Tile {
-            blocked: false,
-            explored: false,
-            block_sight: false,
-        }
-    }
-
-    pub fn wall() -> Self {
-        Tile {
-            blocked: true,
-            explored: false,
-            block_sight: true,
-        }
-    }
-}
-
-type Map = Vec<Vec<Tile>>;
-
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
-}
-impl Rect {
-    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
-        Rect {
-            x1: x,
-            y1: y,
-            x2: x + width,
-            y2: y + height,
-        }
-    }
-
-    pub fn center(&self) -> (i32, i32) {
-        let center_x = (self.x1 + self.x2) / 2;
-        let center_y = (self.y1 + self.y2) / 2;
-        (center_x, center_y)
-    }
-
-    pub fn intersects(&self, other: &Rect) -> bool {
-        self.x1 <= other.x2 && self.x2 >= other.x1 && self.y1 <= other.y2 && self.y2 >= other.y1
-    }
-}
-
-struct Game {
-    map: Map,
-    messages: Messages,
-    inventory: Vec<Object>,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Item {
-    Heal,
-    Lightning,
-    Confuse,
-}
-
-enum UseResult {
-    UsedUp,
-    Cancelled,
+    bindings: Bindings,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -283,438 +83,146 @@ enum PlayerAction {
     Exit,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Fighter {
-    max_hp: i32,
-    hp: i32,
-    defense: i32,
-    power: i32,
-    on_death: DeathCallback,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum DeathCallback {
-    Player,
-    Monster,
-}
-impl DeathCallback {
-    pub fn callback(self, object: &mut Object, game: &mut Game) {
-        use DeathCallback::*;
-        let callback: fn(&mut Object, &mut Game) = match self {
-            Player => DeathCallback::player_death,
-            Monster => DeathCallback::monster_death,
-        };
-        callback(object, game);
-    }
-
-    fn player_death(player: &mut Object, game: &mut Game) {
-        game.messages.add("You died!", colors::RED);
-        player.char = '%';
-        player.color = colors::DARK_RED;
-    }
-
-    fn monster_death(monster: &mut Object, game: &mut Game) {
-        game.messages.add(
-            format!(
-                "{} is dead! You gain {} experience points.",
-                monster.name,
-                monster.fighter.unwrap().max_hp
-            ),
-            colors::ORANGE,
-        );
-        monster.char = '%';
-        monster.color = colors::DARK_RED;
-        monster.blocks = false;
-        monster.fighter = None;
-        monster.ai = None;
-        monster.name = format!("remains of {}", monster.name);
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
-enum Ai {
-    Basic,
-    Confused {
-        previous_ai: Box<Ai>,
-        num_turns: i32,
-    },
-}
-
-fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
-    assert!(first_index != second_index);
-    let split_at_index = cmp::max(first_index, second_index);
-    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
-    if first_index < second_index {
-        (&mut first_slice[first_index], &mut second_slice[0])
-    } else {
-        (&mut second_slice[0], &mut first_slice[second_index])
-    }
-}
-
-fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
-    if map[x as usize][y as usize].blocked {
-        return true;
-    }
-    objects
-        .iter()
-        .any(|object| object.blocks && object.position() == (x, y))
-}
-
-fn create_room(room: Rect, map: &mut Map) {
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
-        }
-    }
-}
-
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
-    for x in min(x1, x2)..=max(x1, x2) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
-}
-
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
-    for y in min(y1, y2)..=max(y1, y2) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
-}
-
-fn make_map(objects: &mut Vec<Object>) -> Map {
-    let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-    let mut rooms = vec![];
-
-    for _ in 0..MAX_ROOMS {
-        let width = rand::thread_rng().gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
-        let height = rand::thread_rng().gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
-        let x = rand::thread_rng().gen_range(0..MAP_WIDTH - width);
-        let y = rand::thread_rng().gen_range(0..MAP_HEIGHT - height);
-
-        let new_room = Rect::new(x, y, width, height);
-        let failed = rooms
-            .iter()
-            .any(|other_room| new_room.intersects(other_room));
-        if failed {
-            continue;
-        }
-
-        create_room(new_room, &mut map);
-        place_objects(new_room, &map, objects);
-        let (new_x, new_y) = new_room.center();
-        if rooms.is_empty() {
-            objects[PLAYER].set_position(new_x, new_y);
-        } else {
-            let (prev_x, prev_y) = rooms[rooms.len() - 1].center();
-            if rand::random() {
-                create_h_tunnel(prev_x, new_x, prev_y, &mut map);
-                create_v_tunnel(prev_y, new_y, new_x, &mut map);
-            } else {
-                create_v_tunnel(prev_y, new_y, prev_x, &mut map);
-                create_h_tunnel(prev_x, new_x, new_y, &mut map);
+/// Returns `None` if the player cancelled with Escape or a right-click.
+fn target_tile(
+    tcod: &mut Tcod,
+    objects: &[Object],
+    game: &Game,
+    max_range: i32,
+) -> Option<(i32, i32)> {
+    let in_range = |(x, y): (i32, i32)| item::target_in_range(x, y, max_range, game, objects);
+
+    let mut cursor = item::closest_monster(max_range, game, objects)
+        .map_or(objects[PLAYER].position(), |id| objects[id].position());
+    while !tcod.root.window_closed() {
+        let mut confirmed = false;
+        match input::check_for_event(input::KEY_PRESS | input::MOUSE) {
+            Some((_, Event::Mouse(m))) => {
+                tcod.mouse = m;
+                cursor = (m.cx as i32, m.cy as i32);
+                if m.rbutton_pressed {
+                    return None;
+                }
+                confirmed = m.lbutton_pressed;
+            }
+            Some((_, Event::Key(k))) => {
+                let (dx, dy) = match k.code {
+                    KeyCode::Escape => return None,
+                    KeyCode::Enter => {
+                        confirmed = true;
+                        (0, 0)
+                    }
+                    _ => tcod
+                        .bindings
+                        .action(k)
+                        .and_then(Action::direction)
+                        .unwrap_or((0, 0)),
+                };
+                cursor = (
+                    (cursor.0 + dx).clamp(0, MAP_WIDTH - 1),
+                    (cursor.1 + dy).clamp(0, MAP_HEIGHT - 1),
+                );
             }
+            None => {}
         }
 
-        rooms.push(new_room);
-    }
-
-    map
-}
-
-fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>) {
-    let num_monsters = rand::thread_rng().gen_range(0..=MAX_ROOM_MONSTERS);
-    for _ in 0..num_monsters {
-        let x = rand::thread_rng().gen_range(room.x1 + 1..room.x2);
-        let y = rand::thread_rng().gen_range(room.y1 + 1..room.y2);
-        if is_blocked(x, y, &map, objects) {
-            continue;
+        if confirmed && in_range(cursor) {
+            return Some(cursor);
         }
-        let mut monster = if rand::random::<f32>() < 0.8 {
-            let mut orc = Object::new(x, y, 'o', "Orc", colors::DESATURATED_GREEN, true);
-            orc.fighter = Some(Fighter {
-                max_hp: 10,
-                hp: 10,
-                defense: 0,
-                power: 3,
-                on_death: DeathCallback::Monster,
-            });
-            orc.ai = Some(Ai::Basic);
-            orc
-        } else {
-            let mut troll = Object::new(x, y, 'T', "Troll", colors::DARKER_GREEN, true);
-            troll.fighter = Some(Fighter {
-                max_hp: 16,
-                hp: 16,
-                defense: 1,
-                power: 4,
-                on_death: DeathCallback::Monster,
-            });
-            troll.ai = Some(Ai::Basic);
-            troll
-        };
-        monster.alive = true;
-        objects.push(monster);
-    }
 
-    let num_items = rand::thread_rng().gen_range(0..MAX_ROOM_ITEMS);
-    for _ in 0..num_items {
-        let x = rand::thread_rng().gen_range(room.x1 + 1..room.x2);
-        let y = rand::thread_rng().gen_range(room.y1 + 1..room.y2);
-        if is_blocked(x, y, &map, objects) {
-            continue;
+        tcod.console.clear();
+        render_all(tcod, objects, game);
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if in_range((x, y)) {
+                    tcod.root
+                        .set_char_background(x, y, COLOR_TARGET_RANGE, BackgroundFlag::Add);
+                }
+            }
         }
-        let dice = rand::random::<f32>();
-        let item = if dice < 0.7 {
-            let mut object = Object::new(x, y, '!', "Healing potion", colors::VIOLET, false);
-            object.item = Some(Item::Heal);
-            object
-        } else if dice < 0.7 + 0.1 {
-            let mut object = Object::new(
-                x,
-                y,
-                '#',
-                "Scroll of Lightning Bolt",
-                colors::LIGHT_YELLOW,
-                false,
-            );
-            object.item = Some(Item::Lightning);
-            object
+        let cursor_color = if in_range(cursor) {
+            COLOR_TARGET_VALID
         } else {
-            let mut object = Object::new(
-                x,
-                y,
-                '#',
-                "Scroll of Confusion",
-                colors::LIGHT_YELLOW,
-                false,
-            );
-            object.item = Some(Item::Confuse);
-            object
+            COLOR_TARGET_INVALID
         };
-        objects.push(item);
-    }
-}
-
-fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<Object>) {
-    if game.inventory.len() >= 26 {
-        game.messages.add(
-            format!(
-                "Your inventory is full, cannot pick up {}.",
-                objects[object_id].name
-            ),
-            colors::RED,
-        );
-    } else {
-        let item = objects.swap_remove(object_id);
-        game.messages
-            .add(format!("You picked up a {}!", item.name), colors::GREEN);
-        game.inventory.push(item);
-    }
-}
-
-fn cast_heal(
-    _inventory_id: usize,
-    _tcod: &mut Tcod,
-    objects: &mut [Object],
-    game: &mut Game,
-) -> UseResult {
-    let player = &mut objects[PLAYER];
-    if let Some(fighter) = player.fighter {
-        if fighter.hp == fighter.max_hp {
-            game.messages
-                .add("You are already at full health.", colors::RED);
-            return UseResult::Cancelled;
-        }
-        game.messages
-            .add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
-        player.heal(HEAL_AMOUNT);
-        UseResult::UsedUp
-    } else {
-        UseResult::Cancelled
-    }
-}
-
-fn closest_monster(tcod: &Tcod, max_range: i32, objects: &[Object]) -> Option<usize> {
-    let mut closest_enemy = None;
-    let mut closest_dist = (max_range + 1) as f32;
-    for (id, object) in objects.iter().enumerate() {
-        if id != PLAYER
-            && object.fighter.is_some()
-            && object.ai.is_some()
-            && tcod.fov.is_in_fov(object.x, object.y)
-        {
-            let dist = objects[PLAYER].distance_to(object);
-            if dist < closest_dist {
-                closest_enemy = Some(id);
-                closest_dist = dist;
-            }
-        }
+        tcod.root
+            .set_char_background(cursor.0, cursor.1, cursor_color, BackgroundFlag::Set);
+        tcod.root.flush();
     }
-    closest_enemy
+    None
 }
 
-fn cast_lightning(
-    _inventory_id: usize,
+fn target_monster(
     tcod: &mut Tcod,
-    objects: &mut [Object],
-    game: &mut Game,
-) -> UseResult {
-    let monster_id = closest_monster(tcod, LIGHTNING_RANGE, objects);
-    if let Some(monster_id) = monster_id {
-        game.messages.add(
-            format!(
-                "A lightning bolt strikes the {} with a loud thunder! \
-                The damage is {} hit points.",
-                objects[monster_id].name, LIGHTNING_DAMAGE
-            ),
-            colors::LIGHT_BLUE,
-        );
-        objects[monster_id].take_damage(LIGHTNING_DAMAGE, game);
-        UseResult::UsedUp
-    } else {
-        game.messages
-            .add("No enemy is close enough to strike.", colors::RED);
-        UseResult::Cancelled
+    objects: &[Object],
+    game: &Game,
+    max_range: i32,
+) -> Option<usize> {
+    loop {
+        let (x, y) = target_tile(tcod, objects, game, max_range)?;
+        if let Some(monster_id) = item::monster_at(x, y, objects) {
+            return Some(monster_id);
+        }
     }
 }
 
-fn cast_confuse(
-    _inventory_id: usize,
+/// Asks the player for whatever the item needs to be aimed at. The outer `None`
+/// means they cancelled, the inner one that the item needs no target.
+fn choose_item_target(
+    inventory_id: usize,
     tcod: &mut Tcod,
-    objects: &mut [Object],
     game: &mut Game,
-) -> UseResult {
-    let monster_id = closest_monster(tcod, CONFUSE_RANGE, objects);
-    if let Some(monster_id) = monster_id {
-        let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
-        objects[monster_id].ai = Some(Ai::Confused {
-            previous_ai: Box::new(old_ai),
-            num_turns: CONFUSE_NUM_TURNS,
-        });
-        game.messages.add(
-            format!(
-                "The eyes of {} look vacant, as he starts to stumble around!",
-                objects[monster_id].name
-            ),
-            colors::LIGHT_GREEN,
-        );
-        UseResult::UsedUp
-    } else {
-        game.messages
-            .add("No enemy is close enough to strike.", colors::RED);
-        UseResult::Cancelled
-    }
-}
-
-fn player_move_or_attack(dx: i32, dy: i32, game: &mut Game, objects: &mut [Object]) {
-    let x = objects[PLAYER].x + dx;
-    let y = objects[PLAYER].y + dy;
-    let target_id = objects
-        .iter()
-        .position(|object| object.fighter.is_some() && object.position() == (x, y));
-    match target_id {
-        Some(target_id) => {
-            let (player, target) = mut_two(PLAYER, target_id, objects);
-            player.attack(target, game);
-        }
-        None => {
-            Object::move_by(PLAYER, dx, dy, &game.map, objects);
-        }
-    }
-}
-
-fn ai_take_turn(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [Object]) {
-    use Ai::*;
-    if let Some(ai) = objects[monster_id].ai.take() {
-        let new_ai = match ai {
-            Basic => ai_basic(monster_id, tcod, game, objects),
-            Confused {
-                previous_ai,
-                num_turns,
-            } => ai_confused(monster_id, tcod, game, objects, previous_ai, num_turns),
-        };
-        objects[monster_id].ai = Some(new_ai);
-    }
-}
-
-fn ai_basic(monster_id: usize, tcod: &Tcod, game: &mut Game, objects: &mut [Object]) -> Ai {
-    let (monster_x, monster_y) = objects[monster_id].position();
-    if tcod.fov.is_in_fov(monster_x, monster_y) {
-        if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
-            let (player_x, player_y) = objects[PLAYER].position();
-            Object::move_towards(monster_id, player_x, player_y, &game.map, objects);
-        } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
-            let (monster, player) = mut_two(monster_id, PLAYER, objects);
-            monster.attack(player, game);
+    objects: &[Object],
+) -> Option<Option<(i32, i32)>> {
+    let targeting = game.inventory[inventory_id]
+        .item
+        .and_then(|item| item.targeting());
+    match targeting {
+        None => Some(None),
+        Some(Targeting::Monster { range }) => {
+            game.messages.add(
+                "Select an enemy with the mouse, or the arrow keys and Enter. \
+                 Right-click or Escape to cancel.",
+                colors::LIGHT_CYAN,
+            );
+            target_monster(tcod, objects, game, range).map(|id| Some(objects[id].position()))
         }
-    }
-    Ai::Basic
-}
-
-fn ai_confused(
-    monster_id: usize,
-    _tcod: &Tcod,
-    game: &mut Game,
-    objects: &mut [Object],
-    previous_ai: Box<Ai>,
-    num_turns: i32,
-) -> Ai {
-    if num_turns >= 0 {
-        Object::move_by(
-            monster_id,
-            rand::thread_rng().gen_range(-1..=1),
-            rand::thread_rng().gen_range(-1..=1),
-            &game.map,
-            objects,
-        );
-        Ai::Confused {
-            previous_ai: previous_ai,
-            num_turns: num_turns - 1,
+        Some(Targeting::Tile { range }) => {
+            game.messages.add(
+                "Select a target tile with the mouse, or the arrow keys and Enter. \
+                 Right-click or Escape to cancel.",
+                colors::LIGHT_CYAN,
+            );
+            target_tile(tcod, objects, game, range).map(Some)
         }
-    } else {
-        game.messages.add(
-            format!("The {} is no longer confused!", objects[monster_id].name),
-            colors::RED,
-        );
-        *previous_ai
     }
 }
 
-fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recompute: bool) {
-    if fov_recompute {
-        let player = &objects[PLAYER];
-        tcod.fov.compute_fov(
-            player.x,
-            player.y,
-            TORCH_RADIUS,
-            FOV_LIGHT_WALLS,
-            FOV_ALGORITHM,
-        );
-    }
-
+fn render_all(tcod: &mut Tcod, objects: &[Object], game: &Game) {
     let mut to_draw: Vec<_> = objects
         .iter()
-        .filter(|o| tcod.fov.is_in_fov(o.x, o.y))
+        .filter(|o| {
+            game.fov.is_in_fov(o.x, o.y)
+                || (o.always_visible && game.map[o.x as usize][o.y as usize].explored)
+        })
         .collect();
     to_draw.sort_by(|o1, o2| o1.blocks.cmp(&o2.blocks));
     for object in &to_draw {
-        if tcod.fov.is_in_fov(object.x, object.y) {
-            object.draw(&mut tcod.console);
-        }
+        tcod.console.set_default_foreground(object.color);
+        tcod.console
+            .put_char(object.x, object.y, object.char, BackgroundFlag::None);
     }
 
     for y in 0..MAP_HEIGHT {
         for x in 0..MAP_WIDTH {
-            let visible = tcod.fov.is_in_fov(x, y);
-            let tile = &mut game.map[x as usize][y as usize];
+            let visible = game.fov.is_in_fov(x, y);
+            let tile = &game.map[x as usize][y as usize];
             let color = match (visible, tile.blocked) {
                 (false, true) => COLOR_DARK_WALL,
                 (false, false) => COLOR_DARK_GROUND,
                 (true, true) => COLOR_LIGHT_WALL,
                 (true, false) => COLOR_LIGHT_GROUND,
             };
-            if visible {
-                tile.explored = true;
-            }
             if tile.explored {
                 tcod.console
                     .set_char_background(x, y, color, BackgroundFlag::Set);
@@ -736,7 +244,7 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
     tcod.panel.clear();
 
     let hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
-    let max_hp = objects[PLAYER].fighter.map_or(0, |f| f.max_hp);
+    let max_hp = objects[PLAYER].max_hp(game);
     render_bar(
         &mut tcod.panel,
         1,
@@ -749,25 +257,62 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
         colors::DARKER_RED,
     );
 
+    let level = objects[PLAYER].level;
+    let xp = objects[PLAYER].fighter.map_or(0, |f| f.xp);
+    render_bar(
+        &mut tcod.panel,
+        1,
+        2,
+        BAR_WIDTH,
+        &format!("Level {} XP", level),
+        xp,
+        game::level_up_xp(level),
+        colors::LIGHT_VIOLET,
+        colors::DARKER_VIOLET,
+    );
+
     let mut y = MSG_HEIGHT;
-    for &(ref msg, color) in game.messages.iter().rev() {
-        let msg_height = tcod.panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+    for message in game.messages.iter().rev() {
+        let msg = message.display();
+        let msg_height = tcod.panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, &msg);
         y -= msg_height;
         if y < 0 {
             break;
         }
-        tcod.panel.set_default_foreground(color);
+        tcod.panel.set_default_foreground(message.color);
         tcod.panel.print_rect(MSG_X, y, MSG_WIDTH, 0, msg);
     }
 
     tcod.panel.set_default_foreground(colors::LIGHT_GREY);
+    tcod.panel.print_ex(
+        1,
+        0,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        get_names_under_mouse(tcod.mouse, objects, &game.fov),
+    );
+
     tcod.panel.print_ex(
         1,
         3,
         BackgroundFlag::None,
         TextAlignment::Left,
-        get_names_under_mouse(tcod.mouse, objects, &tcod.fov),
+        format!("Dungeon level: {}", game.dungeon_level),
     );
+    tcod.panel.print_ex(
+        1,
+        4,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!("Seed: {}", game.seed),
+    );
+    let effects = objects[PLAYER]
+        .effects
+        .iter()
+        .map(|e| format!("{} {}", e.kind.name(), e.turns))
+        .collect::<Vec<_>>()
+        .join(", ");
+    tcod.panel.print_rect(1, 5, BAR_WIDTH, 2, effects);
 
     blit(
         &tcod.panel,
@@ -818,7 +363,13 @@ fn get_names_under_mouse(mouse: Mouse, objects: &[Object], fov_map: &FovMap) ->
             object.position() == (mouse.cx as i32, mouse.cy as i32)
                 && fov_map.is_in_fov(object.x, object.y)
         })
-        .map(|object| object.name.clone())
+        .map(|object| {
+            if object.ai.is_some() && object.awareness == Awareness::Asleep {
+                format!("{} (asleep)", object.name)
+            } else {
+                object.name.clone()
+            }
+        })
         .collect::<Vec<_>>()
         .join(", ")
 }
@@ -827,108 +378,140 @@ fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) -> P
     use PlayerAction::*;
 
     let player_alive = objects[PLAYER].alive;
+    let action = match tcod.bindings.action(tcod.key) {
+        Some(action) => action,
+        None => return DidntTakeTurn,
+    };
 
-    match (tcod.key, tcod.key.text(), player_alive) {
-        (
-            Key {
-                code: KeyCode::Enter,
-                alt: true,
-                ..
-            },
-            _,
-            _,
-        ) => {
+    let command = match (action, player_alive) {
+        (Action::ToggleFullscreen, _) => {
             let fullscreen = tcod.root.is_fullscreen();
             tcod.root.set_fullscreen(!fullscreen);
-            DidntTakeTurn
-        }
-        (
-            Key {
-                code: KeyCode::Escape,
-                ..
-            },
-            _,
-            _,
-        ) => Exit,
-        (
-            Key {
-                code: KeyCode::Up, ..
-            },
-            _,
-            true,
-        ) => {
-            player_move_or_attack(0, -1, game, objects);
-            TookTurn
+            return DidntTakeTurn;
         }
-        (
-            Key {
-                code: KeyCode::Down,
-                ..
-            },
-            _,
-            true,
-        ) => {
-            player_move_or_attack(0, 1, game, objects);
-            TookTurn
+        (Action::Exit, _) => return Exit,
+        (Action::MessageLog, _) => {
+            message_log(tcod, &game.messages);
+            return DidntTakeTurn;
         }
-        (
-            Key {
-                code: KeyCode::Left,
-                ..
-            },
-            _,
-            true,
-        ) => {
-            player_move_or_attack(-1, 0, game, objects);
-            TookTurn
-        }
-        (
-            Key {
-                code: KeyCode::Right,
-                ..
-            },
-            _,
-            true,
-        ) => {
-            player_move_or_attack(1, 0, game, objects);
-            TookTurn
-        }
-        (
-            Key {
-                code: KeyCode::Text,
-                ..
-            },
-            "g",
-            true,
-        ) => {
-            let item_id = objects.iter().position(|object| {
-                object.item.is_some() && object.position() == objects[PLAYER].position()
-            });
-            if let Some(item_id) = item_id {
-                pick_item_up(item_id, game, objects);
+        (Action::PickUp, true) => Command::PickUp,
+        (Action::Descend, true) => Command::Descend,
+        (Action::Wait, true) => Command::Wait,
+        (Action::Inventory, true) => {
+            let inventory_id = inventory_menu(
+                &game.inventory,
+                "Press the key next to an item to use it, or any other to cancel.\n",
+                &mut tcod.root,
+            );
+            let inventory_id = match inventory_id {
+                Some(inventory_id) => inventory_id,
+                None => return DidntTakeTurn,
+            };
+            match choose_item_target(inventory_id, tcod, game, objects) {
+                Some(target) => Command::UseItem {
+                    inventory_id,
+                    target,
+                },
+                None => {
+                    game.messages.add("Cancelled", colors::WHITE);
+                    return DidntTakeTurn;
+                }
             }
-            DidntTakeTurn
         }
-        (
-            Key {
-                code: KeyCode::Text,
-                ..
-            },
-            "i",
-            true,
-        ) => {
+        (Action::Drop, true) => {
             let inventory_id = inventory_menu(
                 &game.inventory,
-                "Press the key next to an item to use it, or any other to cancel.\n",
+                "Press the key next to an item to drop it, or any other to cancel.\n",
                 &mut tcod.root,
             );
-            if let Some(inventory_id) = inventory_id {
-                use_item(inventory_id, tcod, objects, game);
+            match inventory_id {
+                Some(inventory_id) => Command::Drop { inventory_id },
+                None => return DidntTakeTurn,
+            }
+        }
+        (action, true) => match action.direction() {
+            Some((dx, dy)) => Command::MoveOrAttack { dx, dy },
+            None => return DidntTakeTurn,
+        },
+        (_, false) => return DidntTakeTurn,
+    };
+
+    if game::play_turn(command, game, objects) {
+        TookTurn
+    } else {
+        DidntTakeTurn
+    }
+}
+
+/// Shows every retained message, newest at the bottom, until Escape or the log key
+/// is pressed again.
+fn message_log(tcod: &mut Tcod, messages: &Messages) {
+    let total = messages.iter().count() as i32;
+    // How many of the newest messages are scrolled out of view below the bottom.
+    let mut scroll = 0;
+    while !tcod.root.window_closed() {
+        tcod.root.set_default_background(colors::BLACK);
+        tcod.root.clear();
+        tcod.root.set_default_foreground(colors::LIGHT_GREY);
+        tcod.root.print_ex(
+            SCREEN_WIDTH / 2,
+            0,
+            BackgroundFlag::None,
+            TextAlignment::Center,
+            "Message log: arrows or PageUp/PageDown to scroll, Escape to close",
+        );
+
+        let mut y = SCREEN_HEIGHT;
+        for message in messages.iter().rev().skip(scroll as usize) {
+            let msg = message.display();
+            y -= tcod.root.get_height_rect(1, 0, SCREEN_WIDTH - 2, 0, &msg);
+            if y < LOG_TOP {
+                break;
             }
-            TookTurn
+            tcod.root.set_default_foreground(message.color);
+            tcod.root.print_rect(1, y, SCREEN_WIDTH - 2, 0, msg);
+        }
+        tcod.root.flush();
+
+        let key = tcod.root.wait_for_keypress(true);
+        let page = SCREEN_HEIGHT - LOG_TOP;
+        scroll = match key.code {
+            KeyCode::Up => scroll + 1,
+            KeyCode::Down => scroll - 1,
+            KeyCode::PageUp => scroll + page,
+            KeyCode::PageDown => scroll - page,
+            KeyCode::Home => total,
+            KeyCode::End => 0,
+            KeyCode::Escape => break,
+            _ if tcod.bindings.action(key) == Some(Action::MessageLog) => break,
+            _ => scroll,
+        }
+        .clamp(0, (total - 1).max(0));
+    }
+}
+
+/// Keeps asking until a stat is picked, or returns `None` if the window is closed.
+fn level_up_menu(tcod: &mut Tcod, objects: &[Object]) -> Option<Stat> {
+    let fighter = objects[PLAYER].fighter.unwrap();
+    while !tcod.root.window_closed() {
+        let choice = menu(
+            "Level up! Choose a stat to raise:\n",
+            &[
+                format!("Constitution (+20 HP, from {})", fighter.base_max_hp),
+                format!("Strength (+1 attack, from {})", fighter.base_power),
+                format!("Agility (+1 defense, from {})", fighter.base_defense),
+            ],
+            LEVEL_SCREEN_WIDTH,
+            &mut tcod.root,
+        );
+        match choice {
+            Some(0) => return Some(Stat::Constitution),
+            Some(1) => return Some(Stat::Strength),
+            Some(2) => return Some(Stat::Agility),
+            _ => {}
         }
-        _ => DidntTakeTurn,
     }
+    None
 }
 
 fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root) -> Option<usize> {
@@ -937,7 +520,11 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
         "Cannot have a menu with more than 26 options."
     );
 
-    let header_height = root.get_height_rect(0, 0, width, SCREEN_HEIGHT, header);
+    let header_height = if header.is_empty() {
+        0
+    } else {
+        root.get_height_rect(0, 0, width, SCREEN_HEIGHT, header)
+    };
     let height = options.len() as i32 + header_height;
 
     let mut window = Offscreen::new(width, height);
@@ -983,37 +570,180 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
     }
 }
 
+fn msgbox(text: &str, width: i32, root: &mut Root) {
+    let options: &[&str] = &[];
+    menu(text, options, width, root);
+}
+
 fn inventory_menu(inventory: &[Object], header: &str, root: &mut Root) -> Option<usize> {
     let options = inventory
         .iter()
-        .map(|item| item.name.clone())
+        .map(|item| match item.equipment {
+            Some(equipment) if equipment.equipped => {
+                format!("{} (on {})", item.name, equipment.slot)
+            }
+            _ => item.name.clone(),
+        })
         .collect::<Vec<_>>();
     menu(header, &options, INVENTORY_WIDTH, root)
 }
 
-fn use_item(inventory_id: usize, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
-    if let Some(item) = game.inventory[inventory_id].item {
-        let on_use = match item {
-            Item::Heal => cast_heal,
-            Item::Lightning => cast_lightning,
-            Item::Confuse => cast_confuse,
-        };
-        match on_use(inventory_id, tcod, objects, game) {
-            UseResult::UsedUp => {
-                game.inventory.remove(inventory_id);
-                ()
+fn play_game(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) {
+    'turns: while !tcod.root.window_closed() {
+        tcod.console.clear();
+
+        match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
+            Some((_, Event::Mouse(m))) => tcod.mouse = m,
+            Some((_, Event::Key(k))) => tcod.key = k,
+            _ => tcod.key = Default::default(),
+        }
+
+        render_all(tcod, objects, game);
+        tcod.root.flush();
+
+        while game::can_level_up(objects) {
+            match level_up_menu(tcod, objects) {
+                Some(stat) => game::level_up(stat, game, objects),
+                // The window was closed; the level-up is offered again on Continue.
+                None => break 'turns,
             }
-            UseResult::Cancelled => game.messages.add("Cancelled", colors::WHITE),
         }
-    } else {
-        game.messages.add(
-            format!("The {} cannot be used.", game.inventory[inventory_id].name),
-            colors::WHITE,
+
+        let action = handle_keys(tcod, game, objects);
+        if action == PlayerAction::Exit {
+            break;
+        }
+
+        if !objects[PLAYER].alive {
+            let _ = fs::remove_file(SAVE_FILE);
+            tcod.console.clear();
+            render_all(tcod, objects, game);
+            msgbox(
+                "\nYou died! Press any key to return to the main menu.\n",
+                MAIN_MENU_WIDTH,
+                &mut tcod.root,
+            );
+            return;
+        }
+    }
+
+    // Closing the window saves the game just like Escape does.
+    if let Err(e) = game::save_game(game, objects) {
+        eprintln!("Could not save the game: {}", e);
+    }
+}
+
+fn main_menu(tcod: &mut Tcod, seed: Option<u32>, templates: &Templates, message_limit: usize) {
+    while !tcod.root.window_closed() {
+        tcod.root.set_default_background(colors::BLACK);
+        tcod.root.clear();
+        tcod.root.set_default_foreground(colors::LIGHT_YELLOW);
+        tcod.root.print_ex(
+            SCREEN_WIDTH / 2,
+            SCREEN_HEIGHT / 2 - 6,
+            BackgroundFlag::None,
+            TextAlignment::Center,
+            "UNROGUE",
+        );
+        tcod.root.print_ex(
+            SCREEN_WIDTH / 2,
+            SCREEN_HEIGHT / 2 - 4,
+            BackgroundFlag::None,
+            TextAlignment::Center,
+            "Tombs of the Ancient Kings",
         );
+
+        let mut choices = vec!["New Game"];
+        if Path::new(SAVE_FILE).exists() {
+            choices.push("Continue");
+        }
+        choices.push("Quit");
+
+        let choice = menu("", &choices, MAIN_MENU_WIDTH, &mut tcod.root);
+        match choice.map(|index| choices[index]) {
+            Some("New Game") => {
+                let names: Vec<_> = Generator::ALL.iter().map(|g| g.name()).collect();
+                let generator = match menu(
+                    "Choose the dungeon layout:\n",
+                    &names,
+                    MAIN_MENU_WIDTH,
+                    &mut tcod.root,
+                ) {
+                    Some(index) => Generator::ALL[index],
+                    None => continue,
+                };
+                let seed = seed.unwrap_or_else(rand::random);
+                let (mut game, mut objects) =
+                    game::new_game(seed, generator, templates.clone(), message_limit);
+                play_game(tcod, &mut game, &mut objects);
+            }
+            Some("Continue") => match game::load_game() {
+                Ok((mut game, mut objects)) => {
+                    game.messages.set_limit(message_limit);
+                    play_game(tcod, &mut game, &mut objects);
+                }
+                Err(e) => {
+                    msgbox(
+                        &format!("\nCould not load the saved game: {}\n", e),
+                        MAIN_MENU_WIDTH,
+                        &mut tcod.root,
+                    );
+                }
+            },
+            Some("Quit") => break,
+            _ => {}
+        }
+    }
+}
+
+/// Parses the value of a command line argument, or exits saying what is wrong with it.
+fn parse_arg<T: FromStr>(what: &str, arg: Option<String>) -> T
+where
+    T::Err: Display,
+{
+    let arg = match arg {
+        Some(arg) => arg,
+        None => {
+            eprintln!("Missing {}", what);
+            process::exit(1);
+        }
+    };
+    match arg.parse() {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Invalid {} {:?}: {}", what, arg, e);
+            process::exit(1);
+        }
     }
 }
 
 fn main() {
+    // Usage: roguelike [SEED] [--message-limit N]
+    let mut seed = None;
+    let mut message_limit = DEFAULT_MESSAGE_LIMIT;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--message-limit" {
+            message_limit = parse_arg("message limit", args.next());
+        } else {
+            seed = Some(parse_arg("seed", Some(arg)));
+        }
+    }
+    let bindings = match Bindings::load() {
+        Ok(bindings) => bindings,
+        Err(e) => {
+            eprintln!("Could not load key bindings: {}", e);
+            process::exit(1);
+        }
+    };
+    let templates = match Templates::load() {
+        Ok(templates) => templates,
+        Err(e) => {
+            eprintln!("Could not load monster and item templates: {}", e);
+            process::exit(1);
+        }
+    };
+
     set_fps(LIMIT_FPS);
 
     let mut tcod = Tcod {
@@ -1025,69 +755,10 @@ fn main() {
             .init(),
         console: Offscreen::new(MAP_WIDTH, MAP_HEIGHT),
         panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
-        fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
         key: Default::default(),
         mouse: Default::default(),
+        bindings,
     };
 
-    let mut player = Object::new(25, 23, '@', "Player", colors::WHITE, true);
-    player.alive = true;
-    player.fighter = Some(Fighter {
-        max_hp: 30,
-        hp: 30,
-        defense: 2,
-        power: 5,
-        on_death: DeathCallback::Player,
-    });
-    let mut objects = vec![player];
-
-    let mut game = Game {
-        map: make_map(&mut objects),
-        messages: Messages::new(),
-        inventory: vec![],
-    };
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            tcod.fov.set(
-                x,
-                y,
-                !game.map[x as usize][y as usize].block_sight,
-                !game.map[x as usize][y as usize].blocked,
-            );
-        }
-    }
-
-    let mut previous_player_position = (-1, -1);
-
-    game.messages.add(
-        "Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.",
-        colors::RED,
-    );
-
-    while !tcod.root.window_closed() {
-        tcod.console.clear();
-
-        match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
-            Some((_, Event::Mouse(m))) => tcod.mouse = m,
-            Some((_, Event::Key(k))) => tcod.key = k,
-            _ => tcod.key = Default::default(),
-        }
-
-        let fov_recompute = previous_player_position != objects[PLAYER].position();
-        render_all(&mut tcod, &objects, &mut game, fov_recompute);
-        tcod.root.flush();
-
-        previous_player_position = objects[PLAYER].position();
-        let action = handle_keys(&mut tcod, &mut game, &mut objects);
-        if action == PlayerAction::Exit {
-            break;
-        }
-        if objects[PLAYER].alive && action != PlayerAction::DidntTakeTurn {
-            for id in 0..objects.len() {
-                if objects[id].ai.is_some() {
-                    ai_take_turn(id, &tcod, &mut game, &mut objects);
-                }
-            }
-        }
-    }
+    main_menu(&mut tcod, seed, &templates, message_limit);
 }
@@ -0,0 +1,458 @@
+use std::cmp::{max, min};
+use std::mem;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors;
+
+use crate::ai::Awareness;
+use crate::object::{Object, PLAYER};
+use crate::path::distance_map;
+use crate::template::{from_dungeon_level, weighted_choice, Templates};
+
+pub const MAP_WIDTH: i32 = 80;
+pub const MAP_HEIGHT: i32 = 43;
+
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+const MAX_ROOMS: i32 = 30;
+/// Partitions are never split into pieces narrower than this.
+const BSP_MIN_SIZE: i32 = 8;
+/// Chance that a cave tile starts out as rock, and how many times the rock is
+/// smoothed into caverns.
+const CAVE_WALL_CHANCE: f64 = 0.45;
+const CAVE_SMOOTHING_STEPS: u32 = 5;
+/// Caves smaller than this many tiles are dug again.
+const CAVE_MIN_SIZE: usize = 800;
+/// Caves are split into squares this big, and neighbouring squares are grouped until
+/// they have as much room to spawn in as an average room.
+const CAVE_REGION_SIZE: i32 = 10;
+/// Nothing spawns within this many moves of where the player starts in a cave.
+const CAVE_START_CLEARANCE: i32 = 8;
+/// Chance that a monster is asleep when the level is generated.
+const ASLEEP_CHANCE: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Tile {
+    pub blocked: bool,
+    pub explored: bool,
+    pub block_sight: bool,
+}
+impl Tile {
+    pub fn empty() -> Self {
+        Tile {
+            blocked: false,
+            explored: false,
+            block_sight: false,
+        }
+    }
+
+    pub fn wall() -> Self {
+        Tile {
+            blocked: true,
+            explored: false,
+            block_sight: true,
+        }
+    }
+}
+
+pub type Map = Vec<Vec<Tile>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rect {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+}
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + width,
+            y2: y + height,
+        }
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) / 2;
+        let center_y = (self.y1 + self.y2) / 2;
+        (center_x, center_y)
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x1 <= other.x2 && self.x2 >= other.x1 && self.y1 <= other.y2 && self.y2 >= other.y1
+    }
+
+    /// The tiles `create_room` digs out, leaving the edges as walls.
+    pub fn interior(&self) -> Vec<(i32, i32)> {
+        ((self.x1 + 1)..self.x2)
+            .flat_map(|x| ((self.y1 + 1)..self.y2).map(move |y| (x, y)))
+            .collect()
+    }
+}
+
+pub fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    if map[x as usize][y as usize].blocked {
+        return true;
+    }
+    objects
+        .iter()
+        .any(|object| object.blocks && object.position() == (x, y))
+}
+
+pub fn create_room(room: Rect, map: &mut Map) {
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            map[x as usize][y as usize] = Tile::empty();
+        }
+    }
+}
+
+pub fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    for x in min(x1, x2)..=max(x1, x2) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+pub fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    for y in min(y1, y2)..=max(y1, y2) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+/// The ways a level can be laid out. Chosen once for the whole game.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    /// Random rooms, each joined to the one placed before it.
+    #[default]
+    Rooms,
+    /// Rooms in the leaves of a binary space partition, joined to their siblings.
+    Bsp,
+    /// Caverns grown with a cellular automaton.
+    Caves,
+}
+impl Generator {
+    pub const ALL: [Generator; 3] = [Generator::Rooms, Generator::Bsp, Generator::Caves];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Generator::Rooms => "Rooms and corridors",
+            Generator::Bsp => "Partitioned rooms",
+            Generator::Caves => "Caves",
+        }
+    }
+}
+
+/// Digs out a new level and fills `objects` with what is on it. Returns the map
+/// and where its stairs down are.
+pub fn make_map(
+    objects: &mut Vec<Object>,
+    templates: &Templates,
+    generator: Generator,
+    level: u32,
+    rng: &mut impl Rng,
+) -> (Map, (i32, i32)) {
+    let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    objects.truncate(PLAYER + 1);
+
+    let layout = match generator {
+        Generator::Rooms => Layout::from_rooms(&make_rooms(&mut map, rng)),
+        Generator::Bsp => {
+            let mut rooms = vec![];
+            let area = Rect::new(0, 0, MAP_WIDTH - 1, MAP_HEIGHT - 1);
+            make_bsp_rooms(area, &mut map, &mut rooms, rng);
+            Layout::from_rooms(&rooms)
+        }
+        Generator::Caves => make_caves(&mut map, rng),
+    };
+
+    let (start_x, start_y) = layout.start;
+    objects[PLAYER].set_position(start_x, start_y);
+    for area in &layout.spawn_areas {
+        place_objects(area, &map, objects, templates, level, rng);
+    }
+
+    let (stairs_x, stairs_y) = layout.stairs;
+    let mut stairs = Object::new(stairs_x, stairs_y, '>', "stairs", colors::WHITE, false);
+    stairs.always_visible = true;
+    objects.push(stairs);
+
+    (map, layout.stairs)
+}
+
+/// Where things go on a freshly dug level.
+struct Layout {
+    start: (i32, i32),
+    stairs: (i32, i32),
+    /// Each gets `place_objects` called on it once, like a room.
+    spawn_areas: Vec<Vec<(i32, i32)>>,
+}
+impl Layout {
+    /// Starts in the first room and puts the stairs in the last one.
+    fn from_rooms(rooms: &[Rect]) -> Self {
+        Layout {
+            start: rooms[0].center(),
+            stairs: rooms[rooms.len() - 1].center(),
+            spawn_areas: rooms.iter().map(Rect::interior).collect(),
+        }
+    }
+}
+
+fn make_rooms(map: &mut Map, rng: &mut impl Rng) -> Vec<Rect> {
+    let mut rooms = vec![];
+    for _ in 0..MAX_ROOMS {
+        let width = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let height = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let x = rng.gen_range(0..MAP_WIDTH - width);
+        let y = rng.gen_range(0..MAP_HEIGHT - height);
+
+        let new_room = Rect::new(x, y, width, height);
+        let failed = rooms
+            .iter()
+            .any(|other_room| new_room.intersects(other_room));
+        if failed {
+            continue;
+        }
+
+        create_room(new_room, map);
+        if let Some(prev_room) = rooms.last() {
+            connect_rooms(*prev_room, new_room, map, rng);
+        }
+        rooms.push(new_room);
+    }
+    rooms
+}
+
+/// Splits `area` in two until the pieces are too small, puts a room in each piece
+/// that is left and joins the two halves of every split. Returns one of the rooms
+/// inside `area` for the caller to join to its sibling.
+fn make_bsp_rooms(area: Rect, map: &mut Map, rooms: &mut Vec<Rect>, rng: &mut impl Rng) -> Rect {
+    let width = area.x2 - area.x1;
+    let height = area.y2 - area.y1;
+    let can_split_x = width >= 2 * BSP_MIN_SIZE;
+    let can_split_y = height >= 2 * BSP_MIN_SIZE;
+
+    if !can_split_x && !can_split_y {
+        let room_width = rng.gen_range(ROOM_MIN_SIZE..=min(width, ROOM_MAX_SIZE));
+        let room_height = rng.gen_range(ROOM_MIN_SIZE..=min(height, ROOM_MAX_SIZE));
+        let room = Rect::new(
+            rng.gen_range(area.x1..=area.x2 - room_width),
+            rng.gen_range(area.y1..=area.y2 - room_height),
+            room_width,
+            room_height,
+        );
+        create_room(room, map);
+        rooms.push(room);
+        return room;
+    }
+
+    // Cut across the longer side so the pieces stay roughly square.
+    let split_x = can_split_x && (!can_split_y || width > height);
+    let (first, second) = if split_x {
+        let x = rng.gen_range(area.x1 + BSP_MIN_SIZE..=area.x2 - BSP_MIN_SIZE);
+        (Rect { x2: x, ..area }, Rect { x1: x, ..area })
+    } else {
+        let y = rng.gen_range(area.y1 + BSP_MIN_SIZE..=area.y2 - BSP_MIN_SIZE);
+        (Rect { y2: y, ..area }, Rect { y1: y, ..area })
+    };
+    let first_room = make_bsp_rooms(first, map, rooms, rng);
+    let second_room = make_bsp_rooms(second, map, rooms, rng);
+    connect_rooms(first_room, second_room, map, rng);
+    if rng.gen() {
+        first_room
+    } else {
+        second_room
+    }
+}
+
+/// Scatters rock at random and smooths it until it settles into caverns, then
+/// fills in every pocket that can't be reached from the biggest one.
+fn make_caves(map: &mut Map, rng: &mut impl Rng) -> Layout {
+    let distances = loop {
+        for x in 1..MAP_WIDTH - 1 {
+            for y in 1..MAP_HEIGHT - 1 {
+                map[x as usize][y as usize] = if rng.gen_bool(CAVE_WALL_CHANCE) {
+                    Tile::wall()
+                } else {
+                    Tile::empty()
+                };
+            }
+        }
+        for _ in 0..CAVE_SMOOTHING_STEPS {
+            smooth_caves(map);
+        }
+        if let Some((size, distances)) = largest_cave(map) {
+            if size >= CAVE_MIN_SIZE {
+                break distances;
+            }
+        }
+    };
+
+    let mut floor = vec![];
+    for x in 0..MAP_WIDTH {
+        for y in 0..MAP_HEIGHT {
+            if distances[x as usize][y as usize] == i32::MAX {
+                map[x as usize][y as usize] = Tile::wall();
+            } else {
+                floor.push((x, y));
+            }
+        }
+    }
+
+    // The stairs go as far from the start as the cave allows.
+    let start = floor[rng.gen_range(0..floor.len())];
+    let from_start = distance_map(start, map);
+    let stairs = floor
+        .iter()
+        .copied()
+        .max_by_key(|&(x, y)| from_start[x as usize][y as usize])
+        .unwrap();
+
+    // Only spawn in the roomier parts of the cave, not in narrow passages or around
+    // the start. Each spawn area gets as much as a room, so it is made as big as an
+    // average room's floor to keep caves as crowded as rooms and corridors.
+    let open = |(x, y): (i32, i32)| {
+        (-1..=1).all(|dx| (-1..=1).all(|dy| !map[(x + dx) as usize][(y + dy) as usize].blocked))
+    };
+    let room_side = (ROOM_MIN_SIZE + ROOM_MAX_SIZE) / 2 - 1;
+    let room_tiles = (room_side * room_side) as usize;
+    let mut spawn_areas = vec![];
+    let mut area = vec![];
+    for region_x in (0..MAP_WIDTH).step_by(CAVE_REGION_SIZE as usize) {
+        for region_y in (0..MAP_HEIGHT).step_by(CAVE_REGION_SIZE as usize) {
+            area.extend(floor.iter().copied().filter(|&(x, y)| {
+                (region_x..region_x + CAVE_REGION_SIZE).contains(&x)
+                    && (region_y..region_y + CAVE_REGION_SIZE).contains(&y)
+                    && open((x, y))
+                    && from_start[x as usize][y as usize] >= CAVE_START_CLEARANCE
+            }));
+            if area.len() >= room_tiles {
+                spawn_areas.push(mem::take(&mut area));
+            }
+        }
+    }
+
+    Layout {
+        start,
+        stairs,
+        spawn_areas,
+    }
+}
+
+/// One step of the cellular automaton: a tile becomes rock if most of the 3x3
+/// block around it is rock, and floor otherwise. The border stays rock.
+fn smooth_caves(map: &mut Map) {
+    let rock: Vec<Vec<bool>> = map
+        .iter()
+        .map(|column| column.iter().map(|tile| tile.blocked).collect())
+        .collect();
+    for x in 1..MAP_WIDTH - 1 {
+        for y in 1..MAP_HEIGHT - 1 {
+            let nearby_rock = (-1..=1)
+                .flat_map(|dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+                .filter(|&(x, y)| rock[x as usize][y as usize])
+                .count();
+            map[x as usize][y as usize] = if nearby_rock >= 5 {
+                Tile::wall()
+            } else {
+                Tile::empty()
+            };
+        }
+    }
+}
+
+/// The number of tiles in the biggest connected stretch of floor, and the distance
+/// map from a tile inside it, or `None` if there is no floor at all.
+fn largest_cave(map: &Map) -> Option<(usize, Vec<Vec<i32>>)> {
+    let mut seen = vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    let mut largest = None;
+    let mut largest_size = 0;
+    for x in 0..MAP_WIDTH as usize {
+        for y in 0..MAP_HEIGHT as usize {
+            if map[x][y].blocked || seen[x][y] {
+                continue;
+            }
+            let distances = distance_map((x as i32, y as i32), map);
+            let mut size = 0;
+            for (x, column) in distances.iter().enumerate() {
+                for (y, &distance) in column.iter().enumerate() {
+                    if distance != i32::MAX {
+                        seen[x][y] = true;
+                        size += 1;
+                    }
+                }
+            }
+            if size > largest_size {
+                largest_size = size;
+                largest = Some(distances);
+            }
+        }
+    }
+    largest.map(|distances| (largest_size, distances))
+}
+
+/// Digs an L-shaped tunnel between the centers of two rooms.
+fn connect_rooms(from: Rect, to: Rect, map: &mut Map, rng: &mut impl Rng) {
+    let (prev_x, prev_y) = from.center();
+    let (new_x, new_y) = to.center();
+    if rng.gen() {
+        create_h_tunnel(prev_x, new_x, prev_y, map);
+        create_v_tunnel(prev_y, new_y, new_x, map);
+    } else {
+        create_v_tunnel(prev_y, new_y, prev_x, map);
+        create_h_tunnel(prev_x, new_x, new_y, map);
+    }
+}
+
+/// Spawns monsters and items on random tiles of `area`, as many as one room gets.
+pub fn place_objects(
+    area: &[(i32, i32)],
+    map: &Map,
+    objects: &mut Vec<Object>,
+    templates: &Templates,
+    level: u32,
+    rng: &mut impl Rng,
+) {
+    if area.is_empty() {
+        return;
+    }
+    let max_monsters = from_dungeon_level(&templates.max_room_monsters, level);
+    let num_monsters = rng.gen_range(0..=max_monsters);
+    for _ in 0..num_monsters {
+        let (x, y) = area[rng.gen_range(0..area.len())];
+        if is_blocked(x, y, map, objects) {
+            continue;
+        }
+        let template = weighted_choice(
+            &templates.monsters,
+            |m| from_dungeon_level(&m.weight, level),
+            rng,
+        );
+        if let Some(template) = template {
+            let mut monster = template.spawn(x, y);
+            if rng.gen_bool(ASLEEP_CHANCE) {
+                monster.awareness = Awareness::Asleep;
+            }
+            objects.push(monster);
+        }
+    }
+
+    let max_items = from_dungeon_level(&templates.max_room_items, level);
+    let num_items = rng.gen_range(0..=max_items);
+    for _ in 0..num_items {
+        let (x, y) = area[rng.gen_range(0..area.len())];
+        if is_blocked(x, y, map, objects) {
+            continue;
+        }
+        let template = weighted_choice(
+            &templates.items,
+            |i| from_dungeon_level(&i.weight, level),
+            rng,
+        );
+        if let Some(template) = template {
+            objects.push(template.spawn(x, y));
+        }
+    }
+}
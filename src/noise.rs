@@ -0,0 +1,43 @@
+use rand::Rng;
+use tcod::colors;
+
+use crate::ai::{sees_player, Awareness};
+use crate::game::Game;
+use crate::object::Object;
+use crate::path::distance_map;
+
+/// How far, in moves, the noise of the player's actions carries.
+pub const FOOTSTEP_NOISE: i32 = 4;
+pub const COMBAT_NOISE: i32 = 10;
+pub const LIGHTNING_NOISE: i32 = 15;
+pub const FIREBALL_NOISE: i32 = 20;
+
+/// Sends a noise out from `source` through open tiles. Each monster it reaches may
+/// hear it, the more likely the closer it is, and comes to investigate; sleepers
+/// wake up. Monsters already hunting the player in plain sight ignore it.
+pub fn make_noise(source: (i32, i32), loudness: i32, game: &mut Game, objects: &mut [Object]) {
+    let distances = distance_map(source, &game.map);
+    for id in 0..objects.len() {
+        let object = &objects[id];
+        if object.ai.is_none() {
+            continue;
+        }
+        let distance = distances[object.x as usize][object.y as usize];
+        if distance >= loudness {
+            continue;
+        }
+        if matches!(object.awareness, Awareness::Hunting { .. }) && sees_player(id, game, objects) {
+            continue;
+        }
+        let chance = (loudness - distance) as f64 / loudness as f64;
+        if !game.rng.gen_bool(chance) {
+            continue;
+        }
+        let object = &mut objects[id];
+        if object.awareness == Awareness::Asleep && game.fov.is_in_fov(object.x, object.y) {
+            game.messages
+                .add(format!("{} wakes up!", object.name), colors::LIGHT_RED);
+        }
+        object.awareness = Awareness::hunting(source);
+    }
+}
@@ -0,0 +1,454 @@
+use std::cmp;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tcod::colors::{self, Color};
+
+use crate::ai::{Ai, Awareness};
+use crate::effect::{Effect, EffectKind};
+use crate::game::{Game, Messages, NORMAL_SPEED};
+use crate::item::Item;
+use crate::map::{is_blocked, Map, MAP_HEIGHT, MAP_WIDTH};
+use crate::path::find_path;
+
+pub const PLAYER: usize = 0;
+
+/// Paths longer than this are not worth following; the monster just heads straight
+/// for the target instead of wandering off around the whole level.
+const MAX_PATH_LENGTH: usize = 25;
+
+/// Power is multiplied by this when attacking a monster that isn't hunting.
+const SNEAK_ATTACK_MULTIPLIER: i32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Object {
+    pub x: i32,
+    pub y: i32,
+    pub char: char,
+    pub color: Color,
+    pub name: String,
+    pub blocks: bool,
+    pub alive: bool,
+    pub always_visible: bool,
+    pub level: i32,
+    pub fighter: Option<Fighter>,
+    pub ai: Option<Ai>,
+    pub awareness: Awareness,
+    pub item: Option<Item>,
+    pub equipment: Option<Equipment>,
+    pub effects: Vec<Effect>,
+    /// Energy gained per turn before effects; see `game::NORMAL_SPEED`.
+    pub base_speed: i32,
+    /// Spent by acting, and topped up by `speed` every turn.
+    pub energy: i32,
+}
+impl Object {
+    pub fn new(x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
+        Object {
+            x,
+            y,
+            char,
+            color,
+            name: name.into(),
+            blocks,
+            alive: false,
+            always_visible: false,
+            level: 1,
+            fighter: None,
+            ai: None,
+            awareness: Awareness::Wandering { destination: None },
+            item: None,
+            equipment: None,
+            effects: vec![],
+            base_speed: NORMAL_SPEED,
+            energy: 0,
+        }
+    }
+
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    pub fn distance_to(&self, other: &Object) -> f32 {
+        self.distance(other.x, other.y)
+    }
+
+    pub fn distance(&self, x: i32, y: i32) -> f32 {
+        (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
+    }
+
+    pub fn take_damage(&mut self, damage: i32, game: &mut Game) -> Option<i32> {
+        if let Some(fighter) = self.fighter.as_mut() {
+            if damage > 0 {
+                fighter.hp -= damage;
+            }
+        }
+        if damage > 0 && self.awareness == Awareness::Asleep {
+            self.awareness = Awareness::Wandering { destination: None };
+        }
+        if let Some(fighter) = self.fighter {
+            if fighter.hp <= 0 {
+                self.alive = false;
+                fighter.on_death.callback(self, game);
+                return Some(fighter.xp);
+            }
+        }
+        None
+    }
+
+    pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
+        if self.has_effect(EffectKind::Fear) {
+            game.messages.add(
+                format!("{} is too afraid to attack {}!", self.name, target.name),
+                EffectKind::Fear.color(),
+            );
+            return;
+        }
+        // Monsters that don't know the attacker is there can't defend themselves.
+        let unaware = target.ai.is_some() && !matches!(target.awareness, Awareness::Hunting { .. });
+        let damage = if unaware {
+            self.power(game) * SNEAK_ATTACK_MULTIPLIER - target.defense(game)
+        } else {
+            self.power(game) - target.defense(game)
+        };
+        if target.ai.is_some() {
+            target.awareness = Awareness::hunting(self.position());
+        }
+        if damage > 0 {
+            let verb = if unaware { "sneak attacks" } else { "attacks" };
+            game.messages.add(
+                format!(
+                    "{} {} {} for {} hit points.",
+                    self.name, verb, target.name, damage
+                ),
+                colors::WHITE,
+            );
+            if let Some(xp) = target.take_damage(damage, game) {
+                self.fighter.as_mut().unwrap().xp += xp;
+            }
+            if let Some(effect) = self.fighter.and_then(|f| f.on_hit) {
+                if target.alive {
+                    target.apply_effect(effect, game);
+                }
+            }
+        } else {
+            game.messages.add(
+                format!(
+                    "{} attacks {} but it has no effect!",
+                    self.name, target.name
+                ),
+                colors::WHITE,
+            );
+        }
+    }
+
+    /// A ranged attack: like `attack`, but for a fixed damage rather than the
+    /// shooter's power.
+    pub fn shoot(&mut self, target: &mut Object, damage: i32, projectile: &str, game: &mut Game) {
+        let damage = damage - target.defense(game);
+        if damage > 0 {
+            game.messages.add(
+                format!(
+                    "{}'s {} hits {} for {} hit points.",
+                    self.name, projectile, target.name, damage
+                ),
+                colors::WHITE,
+            );
+            target.take_damage(damage, game);
+            if let Some(effect) = self.fighter.and_then(|f| f.on_hit) {
+                if target.alive {
+                    target.apply_effect(effect, game);
+                }
+            }
+        } else {
+            game.messages.add(
+                format!(
+                    "{}'s {} glances off {}.",
+                    self.name, projectile, target.name
+                ),
+                colors::WHITE,
+            );
+        }
+    }
+
+    pub fn heal(&mut self, amount: i32, game: &Game) {
+        let max_hp = self.max_hp(game);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp = cmp::min(max_hp, fighter.hp + amount);
+        }
+    }
+
+    pub fn power(&self, game: &Game) -> i32 {
+        let base_power = self.fighter.map_or(0, |f| f.base_power);
+        let bonus: i32 = self
+            .get_all_equipped(game)
+            .iter()
+            .map(|e| e.power_bonus)
+            .sum();
+        let effect_bonus: i32 = self.effects.iter().map(|e| e.kind.power_bonus()).sum();
+        base_power + bonus + effect_bonus
+    }
+
+    pub fn defense(&self, game: &Game) -> i32 {
+        let base_defense = self.fighter.map_or(0, |f| f.base_defense);
+        let bonus: i32 = self
+            .get_all_equipped(game)
+            .iter()
+            .map(|e| e.defense_bonus)
+            .sum();
+        let effect_bonus: i32 = self.effects.iter().map(|e| e.kind.defense_bonus()).sum();
+        base_defense + bonus + effect_bonus
+    }
+
+    pub fn speed(&self) -> i32 {
+        match (
+            self.has_effect(EffectKind::Haste),
+            self.has_effect(EffectKind::Slow),
+        ) {
+            (true, false) => self.base_speed * 2,
+            (false, true) => self.base_speed / 2,
+            _ => self.base_speed,
+        }
+    }
+
+    pub fn max_hp(&self, game: &Game) -> i32 {
+        let base_max_hp = self.fighter.map_or(0, |f| f.base_max_hp);
+        let bonus: i32 = self
+            .get_all_equipped(game)
+            .iter()
+            .map(|e| e.max_hp_bonus)
+            .sum();
+        base_max_hp + bonus
+    }
+
+    pub fn has_effect(&self, kind: EffectKind) -> bool {
+        self.effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    /// Applying an effect the object already has adds to its remaining turns.
+    pub fn apply_effect(&mut self, effect: Effect, game: &mut Game) {
+        match self.effects.iter_mut().find(|e| e.kind == effect.kind) {
+            Some(existing) => existing.turns += effect.turns,
+            None => self.effects.push(effect),
+        }
+        game.messages.add(
+            format!("{} {}!", self.name, effect.kind.start_message()),
+            effect.kind.color(),
+        );
+    }
+
+    /// Only the player carries an inventory, so monsters never have any equipment.
+    pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
+        if self
+            .fighter
+            .map_or(false, |f| f.on_death == DeathCallback::Player)
+        {
+            game.inventory
+                .iter()
+                .filter_map(|item| item.equipment)
+                .filter(|equipment| equipment.equipped)
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn equip(&mut self, messages: &mut Messages) {
+        if let Some(ref mut equipment) = self.equipment {
+            if !equipment.equipped {
+                equipment.equipped = true;
+                messages.add(
+                    format!("Equipped {} on {}.", self.name, equipment.slot),
+                    colors::LIGHT_GREEN,
+                );
+            }
+        } else {
+            messages.add(
+                format!("Can't equip {} because it's not an Equipment.", self.name),
+                colors::RED,
+            );
+        }
+    }
+
+    pub fn dequip(&mut self, messages: &mut Messages) {
+        if let Some(ref mut equipment) = self.equipment {
+            if equipment.equipped {
+                equipment.equipped = false;
+                messages.add(
+                    format!("Dequipped {} from {}.", self.name, equipment.slot),
+                    colors::LIGHT_YELLOW,
+                );
+            }
+        } else {
+            messages.add(
+                format!("Can't dequip {} because it's not an Equipment.", self.name),
+                colors::RED,
+            );
+        }
+    }
+
+    pub fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
+        let (x, y) = objects[id].position();
+        let (new_x, new_y) = (x + dx, y + dy);
+        if !is_blocked(new_x, new_y, map, objects) {
+            objects[id].set_position(new_x, new_y);
+        }
+    }
+
+    pub fn move_towards(
+        id: usize,
+        target_x: i32,
+        target_y: i32,
+        map: &Map,
+        objects: &mut [Object],
+    ) {
+        let dx = target_x - objects[id].x;
+        let dy = target_y - objects[id].y;
+        let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+        let dx = (dx as f32 / distance).round() as i32;
+        let dy = (dy as f32 / distance).round() as i32;
+        Object::move_by(id, dx, dy, map, objects);
+    }
+
+    /// Steps onto whichever free neighbouring tile is furthest from the target.
+    /// Returns false if none of them gets the object any further away.
+    pub fn move_away(
+        id: usize,
+        target_x: i32,
+        target_y: i32,
+        map: &Map,
+        objects: &mut [Object],
+    ) -> bool {
+        let (x, y) = objects[id].position();
+        let distance_squared = |(x, y): (i32, i32)| (x - target_x).pow(2) + (y - target_y).pow(2);
+        let best = (-1..=1)
+            .flat_map(|dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+            .filter(|&(x, y)| {
+                (0..MAP_WIDTH).contains(&x)
+                    && (0..MAP_HEIGHT).contains(&y)
+                    && !is_blocked(x, y, map, objects)
+            })
+            .max_by_key(|&tile| distance_squared(tile));
+        match best {
+            Some(tile) if distance_squared(tile) > distance_squared((x, y)) => {
+                objects[id].set_position(tile.0, tile.1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn move_astar(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]) {
+        let start = objects[id].position();
+        let path = find_path(start, (target_x, target_y), map, objects);
+        match path {
+            Some(path) if path.len() <= MAX_PATH_LENGTH => {
+                let (x, y) = path[0];
+                if is_blocked(x, y, map, objects) {
+                    Object::move_towards(id, target_x, target_y, map, objects);
+                } else {
+                    objects[id].set_position(x, y);
+                }
+            }
+            _ => Object::move_towards(id, target_x, target_y, map, objects),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fighter {
+    pub base_max_hp: i32,
+    pub hp: i32,
+    pub base_defense: i32,
+    pub base_power: i32,
+    pub xp: i32,
+    pub on_death: DeathCallback,
+    /// Inflicted on whatever this fighter damages.
+    pub on_hit: Option<Effect>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Slot {
+    MainHand,
+    OffHand,
+    Head,
+}
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Slot::MainHand => write!(f, "main hand"),
+            Slot::OffHand => write!(f, "off hand"),
+            Slot::Head => write!(f, "head"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Equipment {
+    pub slot: Slot,
+    /// Defaulted so templates only need to list the bonuses they give.
+    #[serde(default)]
+    pub equipped: bool,
+    #[serde(default)]
+    pub max_hp_bonus: i32,
+    #[serde(default)]
+    pub defense_bonus: i32,
+    #[serde(default)]
+    pub power_bonus: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeathCallback {
+    Player,
+    Monster,
+}
+impl DeathCallback {
+    pub fn callback(self, object: &mut Object, game: &mut Game) {
+        use DeathCallback::*;
+        let callback: fn(&mut Object, &mut Game) = match self {
+            Player => DeathCallback::player_death,
+            Monster => DeathCallback::monster_death,
+        };
+        callback(object, game);
+    }
+
+    fn player_death(player: &mut Object, game: &mut Game) {
+        game.messages.add("You died!", colors::RED);
+        player.char = '%';
+        player.color = colors::DARK_RED;
+    }
+
+    fn monster_death(monster: &mut Object, game: &mut Game) {
+        game.messages.add(
+            format!(
+                "{} is dead! You gain {} experience points.",
+                monster.name,
+                monster.fighter.unwrap().xp
+            ),
+            colors::ORANGE,
+        );
+        monster.char = '%';
+        monster.color = colors::DARK_RED;
+        monster.blocks = false;
+        monster.fighter = None;
+        monster.ai = None;
+        monster.effects.clear();
+        monster.name = format!("remains of {}", monster.name);
+    }
+}
+
+pub fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+    assert!(first_index != second_index);
+    let split_at_index = cmp::max(first_index, second_index);
+    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+    if first_index < second_index {
+        (&mut first_slice[first_index], &mut second_slice[0])
+    } else {
+        (&mut second_slice[0], &mut first_slice[second_index])
+    }
+}
@@ -0,0 +1,245 @@
+use std::cmp::{max, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::map::{is_blocked, Map};
+use crate::object::Object;
+
+/// Extra cost of stepping onto a tile another creature is standing on. They will
+/// probably have moved by then, so it's only worth avoiding, not impassable.
+const OCCUPIED_TILE_COST: i32 = 10;
+
+/// How strongly `safety_map` prefers distance from the threat over the length of
+/// the way there, in tenths. Anything above ten keeps fleeing monsters out of dead
+/// ends once they have room to run.
+const FLEE_COEFFICIENT: i32 = 12;
+
+/// A* over the map with diagonal moves. Returns the tiles to walk through, excluding
+/// `start` and including `goal`, or `None` if walls make `goal` unreachable.
+pub fn find_path(
+    start: (i32, i32),
+    goal: (i32, i32),
+    map: &Map,
+    objects: &[Object],
+) -> Option<Vec<(i32, i32)>> {
+    let width = map.len() as i32;
+    let height = map[0].len() as i32;
+    let index = |(x, y): (i32, i32)| (x * height + y) as usize;
+
+    let mut occupied = vec![false; (width * height) as usize];
+    for object in objects.iter().filter(|object| object.blocks) {
+        occupied[index(object.position())] = true;
+    }
+
+    let mut cost_so_far = vec![i32::MAX; (width * height) as usize];
+    let mut came_from = vec![None; (width * height) as usize];
+    let mut frontier = BinaryHeap::new();
+    cost_so_far[index(start)] = 0;
+    frontier.push(Reverse((distance(start, goal), start)));
+
+    while let Some(Reverse((_, current))) = frontier.pop() {
+        if current == goal {
+            let mut path = vec![goal];
+            let mut tile = goal;
+            while let Some(previous) = came_from[index(tile)] {
+                if previous == start {
+                    break;
+                }
+                path.push(previous);
+                tile = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let next = (current.0 + dx, current.1 + dy);
+                if next == current
+                    || !(0..width).contains(&next.0)
+                    || !(0..height).contains(&next.1)
+                    || map[next.0 as usize][next.1 as usize].blocked
+                {
+                    continue;
+                }
+                let step_cost = if occupied[index(next)] && next != goal {
+                    1 + OCCUPIED_TILE_COST
+                } else {
+                    1
+                };
+                let new_cost = cost_so_far[index(current)] + step_cost;
+                if new_cost < cost_so_far[index(next)] {
+                    cost_so_far[index(next)] = new_cost;
+                    came_from[index(next)] = Some(current);
+                    frontier.push(Reverse((new_cost + distance(next, goal), next)));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Lower values are safer from `threat`. Plain walking distance from the threat
+/// would lead a fleeing monster into the nearest dead end; inverting it and then
+/// spreading it out again makes open areas with room to keep running look best.
+/// Tiles the threat can't reach are `i32::MAX`.
+pub fn safety_map(threat: (i32, i32), map: &Map) -> Vec<Vec<i32>> {
+    let mut values = distance_map(threat, map);
+    for value in values.iter_mut().flatten() {
+        if *value != i32::MAX {
+            *value *= -FLEE_COEFFICIENT;
+        }
+    }
+    spread(&mut values, 10, map);
+    values
+}
+
+/// Number of moves from `start` to every tile, ignoring anything standing in the
+/// way. Tiles that can't be reached are `i32::MAX`.
+pub fn distance_map(start: (i32, i32), map: &Map) -> Vec<Vec<i32>> {
+    let mut values = vec![vec![i32::MAX; map[0].len()]; map.len()];
+    values[start.0 as usize][start.1 as usize] = 0;
+    spread(&mut values, 1, map);
+    values
+}
+
+/// Lowers every tile's value to at most its cheapest neighbour's plus `step_cost`,
+/// working outwards from the tiles that already have one (Dijkstra).
+fn spread(values: &mut [Vec<i32>], step_cost: i32, map: &Map) {
+    let width = map.len() as i32;
+    let height = map[0].len() as i32;
+    let mut frontier = BinaryHeap::new();
+    for (x, column) in values.iter().enumerate() {
+        for (y, &value) in column.iter().enumerate() {
+            if value != i32::MAX {
+                frontier.push(Reverse((value, (x as i32, y as i32))));
+            }
+        }
+    }
+
+    while let Some(Reverse((value, current))) = frontier.pop() {
+        if value > values[current.0 as usize][current.1 as usize] {
+            continue;
+        }
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let next = (current.0 + dx, current.1 + dy);
+                if next == current
+                    || !(0..width).contains(&next.0)
+                    || !(0..height).contains(&next.1)
+                    || map[next.0 as usize][next.1 as usize].blocked
+                {
+                    continue;
+                }
+                let new_value = value + step_cost;
+                if new_value < values[next.0 as usize][next.1 as usize] {
+                    values[next.0 as usize][next.1 as usize] = new_value;
+                    frontier.push(Reverse((new_value, next)));
+                }
+            }
+        }
+    }
+}
+
+/// Number of moves between two tiles when diagonal steps are allowed.
+fn distance(from: (i32, i32), to: (i32, i32)) -> i32 {
+    max((to.0 - from.0).abs(), (to.1 - from.1).abs())
+}
+
+/// The tiles on a straight line from `start` to `end` (Bresenham), excluding
+/// `start` and including `end`.
+pub fn line(start: (i32, i32), end: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = start;
+    let dx = (end.0 - x).abs();
+    let dy = -(end.1 - y).abs();
+    let step_x = (end.0 - x).signum();
+    let step_y = (end.1 - y).signum();
+    let mut error = dx + dy;
+    let mut tiles = vec![];
+    while (x, y) != end {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+        tiles.push((x, y));
+    }
+    tiles
+}
+
+/// Whether a projectile from `start` would reach `end` without hitting a wall or
+/// anything standing in between.
+pub fn has_line_of_fire(start: (i32, i32), end: (i32, i32), map: &Map, objects: &[Object]) -> bool {
+    let tiles = line(start, end);
+    tiles[..tiles.len().saturating_sub(1)]
+        .iter()
+        .all(|&(x, y)| !is_blocked(x, y, map, objects))
+}
+
+/// Whether nothing between `start` and `end` blocks sight. Unlike the player's FOV
+/// this doesn't depend on the viewer's torch or effects.
+pub fn has_line_of_sight(start: (i32, i32), end: (i32, i32), map: &Map) -> bool {
+    let tiles = line(start, end);
+    tiles[..tiles.len().saturating_sub(1)]
+        .iter()
+        .all(|&(x, y)| !map[x as usize][y as usize].block_sight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+
+    fn open_map(width: usize, height: usize) -> Map {
+        vec![vec![Tile::empty(); height]; width]
+    }
+
+    #[test]
+    fn find_path_walks_diagonally() {
+        let map = open_map(10, 10);
+        let path = find_path((0, 0), (3, 3), &map, &[]).unwrap();
+        assert_eq!(path, vec![(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn find_path_goes_through_the_gap_in_a_wall() {
+        let mut map = open_map(10, 10);
+        for y in 0..10 {
+            if y != 8 {
+                map[5][y] = Tile::wall();
+            }
+        }
+        let path = find_path((2, 2), (8, 2), &map, &[]).unwrap();
+        assert!(path.contains(&(5, 8)));
+        assert_eq!(path.last(), Some(&(8, 2)));
+    }
+
+    #[test]
+    fn find_path_gives_up_on_walled_off_goals() {
+        let mut map = open_map(10, 10);
+        for y in 0..10 {
+            map[5][y] = Tile::wall();
+        }
+        assert_eq!(find_path((2, 2), (8, 2), &map, &[]), None);
+    }
+
+    #[test]
+    fn line_excludes_start_and_includes_end() {
+        assert_eq!(line((0, 0), (3, 0)), vec![(1, 0), (2, 0), (3, 0)]);
+        assert_eq!(line((2, 2), (0, 0)), vec![(1, 1), (0, 0)]);
+        assert!(line((4, 4), (4, 4)).is_empty());
+    }
+
+    #[test]
+    fn walls_block_line_of_sight() {
+        let mut map = open_map(10, 10);
+        assert!(has_line_of_sight((0, 0), (9, 0), &map));
+        map[5][0] = Tile::wall();
+        assert!(!has_line_of_sight((0, 0), (9, 0), &map));
+        // Seeing a wall itself is fine.
+        assert!(has_line_of_sight((0, 0), (5, 0), &map));
+    }
+}
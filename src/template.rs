@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tcod::colors::Color;
+
+use crate::ai::Ai;
+use crate::effect::Effect;
+use crate::game::NORMAL_SPEED;
+use crate::item::Item;
+use crate::object::{DeathCallback, Equipment, Fighter, Object};
+
+pub const TEMPLATES_FILE: &str = "templates.toml";
+
+/// Used when there is no templates file next to the game.
+const DEFAULT_TEMPLATES: &str = include_str!("../templates.toml");
+
+/// Everything `place_objects` can put in a room, as read from `TEMPLATES_FILE`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Templates {
+    pub max_room_monsters: Vec<Transition>,
+    pub max_room_items: Vec<Transition>,
+    pub monsters: Vec<MonsterTemplate>,
+    pub items: Vec<ItemTemplate>,
+}
+impl Templates {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let contents = match fs::read_to_string(TEMPLATES_FILE) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => DEFAULT_TEMPLATES.into(),
+            Err(e) => return Err(format!("{}: {}", TEMPLATES_FILE, e).into()),
+        };
+        Templates::parse(&contents).map_err(|e| format!("{}: {}", TEMPLATES_FILE, e).into())
+    }
+
+    fn parse(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let templates: Templates = toml::from_str(contents)?;
+        for item in &templates.items {
+            if (item.item == Item::Equipment) != item.equipment.is_some() {
+                return Err(format!(
+                    "{:?} needs an equipment table if and only if its item is Equipment",
+                    item.name
+                )
+                .into());
+            }
+        }
+        Ok(templates)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MonsterTemplate {
+    pub name: String,
+    pub char: char,
+    pub color: Color,
+    pub fighter: FighterStats,
+    pub ai: Ai,
+    #[serde(default = "default_speed")]
+    pub speed: i32,
+    pub weight: Vec<Transition>,
+}
+impl MonsterTemplate {
+    pub fn spawn(&self, x: i32, y: i32) -> Object {
+        let mut monster = Object::new(x, y, self.char, &self.name, self.color, true);
+        monster.fighter = Some(Fighter {
+            base_max_hp: self.fighter.hp,
+            hp: self.fighter.hp,
+            base_defense: self.fighter.defense,
+            base_power: self.fighter.power,
+            xp: self.fighter.xp,
+            on_death: DeathCallback::Monster,
+            on_hit: self.fighter.on_hit,
+        });
+        monster.ai = Some(self.ai.clone());
+        monster.base_speed = self.speed;
+        monster.alive = true;
+        monster
+    }
+}
+
+fn default_speed() -> i32 {
+    NORMAL_SPEED
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FighterStats {
+    pub hp: i32,
+    pub defense: i32,
+    pub power: i32,
+    pub xp: i32,
+    pub on_hit: Option<Effect>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemTemplate {
+    pub name: String,
+    pub char: char,
+    pub color: Color,
+    pub item: Item,
+    pub equipment: Option<Equipment>,
+    pub weight: Vec<Transition>,
+}
+impl ItemTemplate {
+    pub fn spawn(&self, x: i32, y: i32) -> Object {
+        let mut object = Object::new(x, y, self.char, &self.name, self.color, false);
+        object.item = Some(self.item);
+        object.equipment = self.equipment;
+        object
+    }
+}
+
+/// A value that takes effect from `level` downwards, until a deeper transition.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Transition {
+    pub level: u32,
+    pub value: u32,
+}
+
+/// Looks up the value for `level` in a table of transitions, in any order.
+pub fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
+    table
+        .iter()
+        .filter(|transition| transition.level <= level)
+        .max_by_key(|transition| transition.level)
+        .map_or(0, |transition| transition.value)
+}
+
+/// Picks one of `choices` with a chance proportional to its weight, or `None` if
+/// every weight is zero.
+pub fn weighted_choice<'a, T>(
+    choices: &'a [T],
+    weight: impl Fn(&T) -> u32,
+    rng: &mut impl Rng,
+) -> Option<&'a T> {
+    let distribution = WeightedIndex::new(choices.iter().map(weight)).ok()?;
+    Some(&choices[distribution.sample(rng)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn default_templates_parse() {
+        Templates::parse(DEFAULT_TEMPLATES).unwrap();
+    }
+
+    #[test]
+    fn from_dungeon_level_takes_the_deepest_step_reached() {
+        let table = [
+            Transition { level: 4, value: 3 },
+            Transition { level: 1, value: 2 },
+            Transition { level: 6, value: 5 },
+        ];
+        assert_eq!(from_dungeon_level(&table, 0), 0);
+        assert_eq!(from_dungeon_level(&table, 1), 2);
+        assert_eq!(from_dungeon_level(&table, 3), 2);
+        assert_eq!(from_dungeon_level(&table, 4), 3);
+        assert_eq!(from_dungeon_level(&table, 10), 5);
+    }
+
+    #[test]
+    fn weighted_choice_skips_zero_weights() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let choices = [("never", 0), ("always", 7), ("nope", 0)];
+        for _ in 0..100 {
+            let choice = weighted_choice(&choices, |&(_, weight)| weight, &mut rng);
+            assert_eq!(choice, Some(&("always", 7)));
+        }
+        assert_eq!(weighted_choice(&choices, |_| 0, &mut rng), None);
+    }
+}
@@ -0,0 +1,109 @@
+use roguelike::game::{new_game, play_turn, Command, Game, DEFAULT_MESSAGE_LIMIT};
+use roguelike::item::Item;
+use roguelike::map::Generator;
+use roguelike::object::{Object, PLAYER};
+use roguelike::template::Templates;
+use tcod::colors;
+
+const SEED: u32 = 42;
+
+fn commands() -> Vec<Command> {
+    let mut commands = vec![Command::Wait];
+    for (dx, dy) in [(1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, -1)] {
+        commands.push(Command::MoveOrAttack { dx, dy });
+        commands.push(Command::Wait);
+    }
+    commands
+}
+
+fn play(seed: u32, generator: Generator, commands: &[Command]) -> (Game, Vec<Object>) {
+    let (mut game, mut objects) = new_game(
+        seed,
+        generator,
+        Templates::load().unwrap(),
+        DEFAULT_MESSAGE_LIMIT,
+    );
+    for &command in commands {
+        if !objects[PLAYER].alive {
+            break;
+        }
+        play_turn(command, &mut game, &mut objects);
+    }
+    (game, objects)
+}
+
+#[test]
+fn turns_advance_without_a_window() {
+    let (mut game, mut objects) = new_game(
+        SEED,
+        Generator::default(),
+        Templates::load().unwrap(),
+        DEFAULT_MESSAGE_LIMIT,
+    );
+    // Only the player, in the middle of the first room, so nothing is in the way.
+    objects.truncate(1);
+    let (x, y) = objects[PLAYER].position();
+    assert_eq!(game.turn, 0);
+
+    assert!(play_turn(
+        Command::MoveOrAttack { dx: 1, dy: 0 },
+        &mut game,
+        &mut objects
+    ));
+    assert_eq!(objects[PLAYER].position(), (x + 1, y));
+    assert_eq!(game.turn, 1);
+
+    // Picking things up takes no time.
+    let mut potion = Object::new(x + 1, y, '!', "healing potion", colors::VIOLET, false);
+    potion.item = Some(Item::Heal);
+    objects.push(potion);
+    assert!(!play_turn(Command::PickUp, &mut game, &mut objects));
+    assert_eq!(game.turn, 1);
+    assert_eq!(game.inventory.len(), 1);
+    assert_eq!(objects.len(), 1);
+}
+
+#[test]
+fn same_seed_and_commands_give_the_same_game() {
+    for generator in Generator::ALL {
+        let (game_a, objects_a) = play(SEED, generator, &commands());
+        let (game_b, objects_b) = play(SEED, generator, &commands());
+        assert_eq!(
+            serde_json::to_string(&(&game_a, &objects_a)).unwrap(),
+            serde_json::to_string(&(&game_b, &objects_b)).unwrap(),
+            "{:?} games diverged",
+            generator
+        );
+    }
+}
+
+#[test]
+fn different_seeds_give_different_levels() {
+    let (game_a, _) = play(SEED, Generator::default(), &[]);
+    let (game_b, _) = play(SEED + 1, Generator::default(), &[]);
+    assert_ne!(
+        serde_json::to_string(&game_a.map).unwrap(),
+        serde_json::to_string(&game_b.map).unwrap()
+    );
+}
+
+#[test]
+fn missing_inventory_items_are_ignored() {
+    let (mut game, mut objects) = new_game(
+        SEED,
+        Generator::default(),
+        Templates::load().unwrap(),
+        DEFAULT_MESSAGE_LIMIT,
+    );
+    let use_item = Command::UseItem {
+        inventory_id: 3,
+        target: None,
+    };
+    assert!(!play_turn(use_item, &mut game, &mut objects));
+    assert!(!play_turn(
+        Command::Drop { inventory_id: 0 },
+        &mut game,
+        &mut objects
+    ));
+    assert_eq!(game.turn, 0);
+}